@@ -0,0 +1,445 @@
+use crate::error::{CommandError, CommandResult};
+use crate::hide_console_window;
+use std::path::Path;
+use std::process::Command;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_ALAW: u16 = 6;
+const WAVE_FORMAT_MULAW: u16 = 7;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+pub struct WavInfo {
+    // WAVE_FORMAT_EXTENSIBLE files report their sub-format here instead.
+    pub audio_format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub byte_rate: u32,
+    pub bits_per_sample: u16,
+    pub data_offset: usize,
+    pub data_len: u32,
+}
+
+impl WavInfo {
+    // 16-bit PCM at 16 kHz, which whisper.cpp reads without resampling. It
+    // mixes stereo down itself unless the channels are needed for diarization.
+    pub fn is_whisper_ready(&self, needs_stereo: bool) -> bool {
+        let channels_ok = if needs_stereo {
+            self.channels == 2
+        } else {
+            matches!(self.channels, 1 | 2)
+        };
+        self.audio_format == WAVE_FORMAT_PCM
+            && self.bits_per_sample == 16
+            && self.sample_rate == WHISPER_SAMPLE_RATE
+            && channels_ok
+    }
+
+    // e.g. "WAV (IEEE float), 44100 Hz, 2 channel(s), 32-bit".
+    pub fn describe(&self) -> String {
+        let encoding = match self.audio_format {
+            WAVE_FORMAT_PCM => "PCM".to_string(),
+            WAVE_FORMAT_IEEE_FLOAT => "IEEE float".to_string(),
+            WAVE_FORMAT_ALAW => "A-law".to_string(),
+            WAVE_FORMAT_MULAW => "mu-law".to_string(),
+            other => format!("format 0x{other:04x}"),
+        };
+        format!(
+            "WAV ({encoding}), {} Hz, {} channel(s), {}-bit",
+            self.sample_rate, self.channels, self.bits_per_sample
+        )
+    }
+
+    pub fn duration_ms(&self) -> Option<u64> {
+        if self.byte_rate == 0 {
+            return None;
+        }
+        Some(u64::from(self.data_len) * 1000 / u64::from(self.byte_rate))
+    }
+}
+
+// Header of a WAV file on disk, without reading the samples. The data length
+// comes from the file size, so the duration is right even for long files.
+pub fn read_wav_file_info(path: &Path) -> Option<WavInfo> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path).ok()?;
+    let file_len = file.metadata().ok()?.len();
+    let mut header = Vec::new();
+    file.by_ref()
+        .take(64 * 1024)
+        .read_to_end(&mut header)
+        .ok()?;
+    let mut info = parse_wav_header(&header)?;
+    info.data_len = file_len
+        .saturating_sub(info.data_offset as u64)
+        .min(u64::from(u32::MAX)) as u32;
+    Some(info)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let slice = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([slice[0], slice[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let slice = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+pub fn parse_wav_header(bytes: &[u8]) -> Option<WavInfo> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut format: Option<(u16, u16, u32, u32, u16)> = None;
+    let mut offset = 12;
+    while let (Some(chunk_id), Some(chunk_len)) =
+        (bytes.get(offset..offset + 4), read_u32(bytes, offset + 4))
+    {
+        let body = offset + 8;
+        match chunk_id {
+            b"fmt " => {
+                let mut audio_format = read_u16(bytes, body)?;
+                if audio_format == WAVE_FORMAT_EXTENSIBLE && chunk_len >= 40 {
+                    // The first two bytes of the sub-format GUID hold the tag.
+                    audio_format = read_u16(bytes, body + 24)?;
+                }
+                format = Some((
+                    audio_format,
+                    read_u16(bytes, body + 2)?,
+                    read_u32(bytes, body + 4)?,
+                    read_u32(bytes, body + 8)?,
+                    read_u16(bytes, body + 14)?,
+                ));
+            }
+            b"data" => {
+                let (audio_format, channels, sample_rate, byte_rate, bits_per_sample) = format?;
+                // Streaming writers leave the data length at 0 or u32::MAX; fall
+                // back to whatever is actually present after the header.
+                let available = bytes.len().saturating_sub(body) as u32;
+                let data_len = if chunk_len == 0 || chunk_len > available {
+                    available
+                } else {
+                    chunk_len
+                };
+                return Some(WavInfo {
+                    audio_format,
+                    channels,
+                    sample_rate,
+                    byte_rate,
+                    bits_per_sample,
+                    data_offset: body,
+                    data_len,
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even number of bytes.
+        offset = body + chunk_len as usize + (chunk_len as usize & 1);
+    }
+
+    None
+}
+
+// Identifies common audio containers by their magic bytes, so a mislabeled
+// file can be reported (or converted) as what it really is.
+pub fn sniff_container(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => Some("webm"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("m4a"),
+        // ADTS AAC shares MPEG's sync word but has layer bits 00.
+        [0xff, second, ..] if second & 0xf6 == 0xf0 => Some("aac"),
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        [0xff, second, ..] if second & 0xe0 == 0xe0 => Some("mp3"),
+        _ => None,
+    }
+}
+
+// The samples of a 16 kHz mono 16-bit PCM WAV, or `None` for anything else.
+pub fn mono_pcm16_samples(bytes: &[u8]) -> Option<Vec<i16>> {
+    let info = parse_wav_header(bytes)?;
+    if !info.is_whisper_ready(false) || info.channels != 1 {
+        return None;
+    }
+    let data = bytes.get(info.data_offset..info.data_offset + info.data_len as usize)?;
+    Some(
+        data.chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect(),
+    )
+}
+
+// One channel of a 16 kHz 16-bit PCM WAV as a mono WAV, or `None` when the
+// input isn't in that format or has no such channel.
+pub fn extract_pcm16_channel(bytes: &[u8], channel: u16) -> Option<Vec<u8>> {
+    let info = parse_wav_header(bytes)?;
+    if info.audio_format != WAVE_FORMAT_PCM
+        || info.bits_per_sample != 16
+        || info.sample_rate != WHISPER_SAMPLE_RATE
+        || channel >= info.channels
+    {
+        return None;
+    }
+    let data = bytes.get(info.data_offset..info.data_offset + info.data_len as usize)?;
+    let frame_len = usize::from(info.channels) * 2;
+    let offset = usize::from(channel) * 2;
+    let samples: Vec<i16> = data
+        .chunks_exact(frame_len)
+        .map(|frame| i16::from_le_bytes([frame[offset], frame[offset + 1]]))
+        .collect();
+    Some(pcm16_wav(&samples))
+}
+
+// Peak normalization target (about -1 dBFS) and the most it will amplify
+// (about +24 dB), so near-silence isn't blown up into loud noise.
+const NORMALIZE_TARGET_PEAK: f32 = 29_204.0;
+const NORMALIZE_MAX_GAIN: f32 = 16.0;
+
+// Scales the samples so the loudest one reaches the target peak. Audio that is
+// already loud enough, or entirely silent, is left alone.
+pub fn normalize_peak(samples: &mut [i16]) {
+    let peak = samples
+        .iter()
+        .map(|sample| sample.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    if peak == 0 {
+        return;
+    }
+    let gain = (NORMALIZE_TARGET_PEAK / f32::from(peak)).min(NORMALIZE_MAX_GAIN);
+    if gain <= 1.0 {
+        return;
+    }
+    for sample in samples.iter_mut() {
+        *sample = (f32::from(*sample) * gain)
+            .round()
+            .clamp(-32_768.0, 32_767.0) as i16;
+    }
+}
+
+// A 16-bit PCM WAV with its samples peak-normalized, or `None` for any
+// other format.
+pub fn normalize_pcm16_wav(bytes: &[u8]) -> Option<Vec<u8>> {
+    let info = parse_wav_header(bytes)?;
+    if info.audio_format != WAVE_FORMAT_PCM || info.bits_per_sample != 16 {
+        return None;
+    }
+    let data_range = info.data_offset..info.data_offset + info.data_len as usize;
+    let mut samples: Vec<i16> = bytes
+        .get(data_range.clone())?
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    normalize_peak(&mut samples);
+    let mut normalized = bytes.to_vec();
+    for (pair, sample) in normalized[data_range].chunks_exact_mut(2).zip(samples) {
+        pair.copy_from_slice(&sample.to_le_bytes());
+    }
+    Some(normalized)
+}
+
+// whisper.cpp reads 16 kHz mono 16-bit PCM natively.
+pub const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+pub fn silent_wav(duration_ms: u32) -> Vec<u8> {
+    pcm16_wav(&vec![
+        0;
+        (WHISPER_SAMPLE_RATE / 1000 * duration_ms) as usize
+    ])
+}
+
+// Wraps 16 kHz mono samples in a canonical 44-byte WAV header.
+pub fn pcm16_wav(samples: &[i16]) -> Vec<u8> {
+    const SAMPLE_RATE: u32 = WHISPER_SAMPLE_RATE;
+    const BYTES_PER_SAMPLE: u16 = 2;
+    let byte_rate = SAMPLE_RATE * u32::from(BYTES_PER_SAMPLE);
+    let data_len = samples.len() as u32 * u32::from(BYTES_PER_SAMPLE);
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&BYTES_PER_SAMPLE.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+fn ffmpeg_binary() -> String {
+    std::env::var("GREEPY_FFMPEG_BIN")
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "ffmpeg".to_string())
+}
+
+// Converts any input ffmpeg understands into 16 kHz 16-bit PCM WAV with the
+// requested channel count. With `source_channel` set, only that input channel
+// is kept (as mono) instead of mixing them all down. `normalize` applies
+// ffmpeg's EBU R128 `loudnorm` filter to bring quiet recordings up.
+pub fn convert_with_ffmpeg(
+    input: &Path,
+    output: &Path,
+    channels: u16,
+    source_channel: Option<u16>,
+    normalize: bool,
+) -> CommandResult<()> {
+    let binary = ffmpeg_binary();
+    let mut command = Command::new(&binary);
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y", "-i"])
+        .arg(input);
+    let mut filters = Vec::new();
+    if let Some(channel) = source_channel {
+        filters.push(format!("pan=mono|c0=c{channel}"));
+    }
+    if normalize {
+        filters.push("loudnorm".to_string());
+    }
+    if !filters.is_empty() {
+        command.arg("-af").arg(filters.join(","));
+    }
+    // loudnorm resamples internally, so the rate is set after the filters.
+    let channels = if source_channel.is_some() {
+        1
+    } else {
+        channels
+    };
+    command.args([
+        "-ar",
+        &WHISPER_SAMPLE_RATE.to_string(),
+        "-ac",
+        &channels.to_string(),
+    ]);
+    command.args(["-c:a", "pcm_s16le"]).arg(output);
+    hide_console_window(&mut command);
+    let process_output = command.output().map_err(|error| {
+        CommandError::SpawnFailed(format!(
+            "This audio needs conversion but ffmpeg could not be launched ('{binary}'): {error}. Install ffmpeg or set GREEPY_FFMPEG_BIN."
+        ))
+    })?;
+    if !process_output.status.success() {
+        return Err(CommandError::Io(format!(
+            "ffmpeg failed to convert the audio: {}",
+            String::from_utf8_lossy(&process_output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn fmt_body(audio_format: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&audio_format.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(4 + body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    #[test]
+    fn parses_the_canonical_header() {
+        let info = parse_wav_header(&pcm16_wav(&[0; 1600])).unwrap();
+        assert_eq!(info.audio_format, WAVE_FORMAT_PCM);
+        assert_eq!((info.channels, info.sample_rate), (1, WHISPER_SAMPLE_RATE));
+        assert_eq!((info.byte_rate, info.bits_per_sample), (32_000, 16));
+        assert_eq!((info.data_offset, info.data_len), (44, 3200));
+        assert_eq!(info.duration_ms(), Some(100));
+        assert!(info.is_whisper_ready(false));
+        assert!(!info.is_whisper_ready(true));
+    }
+
+    #[test]
+    fn skips_other_chunks_including_padding() {
+        let wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_IEEE_FLOAT, 2, 44_100, 32)),
+            // Odd length, so a pad byte follows.
+            chunk(b"LIST", b"INFOx"),
+            chunk(b"data", &[0; 8]),
+        ]);
+        let info = parse_wav_header(&wav).unwrap();
+        assert_eq!(info.audio_format, WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!((info.channels, info.sample_rate), (2, 44_100));
+        assert_eq!((info.data_offset, info.data_len), (wav.len() - 8, 8));
+        assert_eq!(
+            info.describe(),
+            "WAV (IEEE float), 44100 Hz, 2 channel(s), 32-bit"
+        );
+    }
+
+    #[test]
+    fn reads_the_sub_format_of_extensible_files() {
+        let mut body = fmt_body(WAVE_FORMAT_EXTENSIBLE, 1, 16_000, 16);
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        body.extend_from_slice(&[0; 14]);
+        let wav = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 4])]);
+        let info = parse_wav_header(&wav).unwrap();
+        assert_eq!(info.audio_format, WAVE_FORMAT_PCM);
+        assert!(info.is_whisper_ready(false));
+    }
+
+    #[test]
+    fn falls_back_to_the_available_data_for_streamed_lengths() {
+        let mut wav = pcm16_wav(&[0; 10]);
+        for placeholder in [0, u32::MAX] {
+            wav[40..44].copy_from_slice(&placeholder.to_le_bytes());
+            assert_eq!(parse_wav_header(&wav).unwrap().data_len, 20);
+        }
+        // A length past the end of a truncated file is capped the same way.
+        wav[40..44].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(parse_wav_header(&wav).unwrap().data_len, 20);
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_usable_wav() {
+        assert!(parse_wav_header(b"").is_none());
+        assert!(parse_wav_header(b"RIFF\0\0\0\0AVI LIST").is_none());
+        // No format before the data.
+        assert!(parse_wav_header(&riff(&[chunk(b"data", &[0; 4])])).is_none());
+        // A format chunk cut short.
+        assert!(parse_wav_header(&riff(&[chunk(b"fmt ", &[1, 0, 1])])).is_none());
+        // No data chunk at all.
+        let format_only = riff(&[chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 16_000, 16))]);
+        assert!(parse_wav_header(&format_only).is_none());
+    }
+}
@@ -0,0 +1,71 @@
+use crate::error::{CommandError, CommandResult};
+use crate::lock_recovering;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const CONFIG_FILE_NAME: &str = "greepy.config.json";
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AppConfig {
+    pub whisper_binary: Option<String>,
+    pub whisper_model_path: Option<String>,
+    pub whisper_language: Option<String>,
+    pub whisper_prefer_model: Option<String>,
+    pub default_shell: Option<String>,
+    pub whisper_max_audio_bytes: Option<u64>,
+    pub max_pty_sessions: Option<usize>,
+    pub whisper_post_hook: Option<String>,
+    pub whisper_redact_dirs: Option<Vec<String>>,
+}
+
+pub struct ConfigState {
+    path: Option<PathBuf>,
+    config: Mutex<AppConfig>,
+}
+
+impl ConfigState {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let config = path
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| match fs::read_to_string(path) {
+                Ok(contents) => serde_json::from_str(&contents)
+                    .map_err(|error| {
+                        tracing::warn!("ignoring invalid {}: {error}", path.display());
+                    })
+                    .ok(),
+                Err(error) => {
+                    tracing::warn!("failed to read {}: {error}", path.display());
+                    None
+                }
+            })
+            .unwrap_or_default();
+        ConfigState {
+            path,
+            config: Mutex::new(config),
+        }
+    }
+
+    pub fn get(&self) -> AppConfig {
+        lock_recovering(&self.config, "config").clone()
+    }
+
+    pub fn set(&self, config: AppConfig) -> CommandResult<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Err(CommandError::Io(
+                "App config directory is unavailable.".to_string(),
+            ));
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(&config)
+            .map_err(|error| CommandError::Io(error.to_string()))?;
+        fs::write(path, contents)?;
+        *lock_recovering(&self.config, "config") = config;
+        Ok(())
+    }
+}
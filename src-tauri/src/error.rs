@@ -0,0 +1,99 @@
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::sync::PoisonError;
+
+#[derive(Debug)]
+pub enum CommandError {
+    SessionNotFound(String),
+    SessionLimitReached(usize),
+    LockPoisoned,
+    SpawnFailed(String),
+    Io(String),
+    InvalidArgument(String),
+    Whisper(String),
+    Timeout(String),
+    Unsupported(String),
+    Disabled,
+}
+
+impl CommandError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CommandError::SessionNotFound(_) => "sessionNotFound",
+            CommandError::SessionLimitReached(_) => "sessionLimitReached",
+            CommandError::LockPoisoned => "lockPoisoned",
+            CommandError::SpawnFailed(_) => "spawnFailed",
+            CommandError::Io(_) => "io",
+            CommandError::InvalidArgument(_) => "invalidArgument",
+            CommandError::Whisper(_) => "whisper",
+            CommandError::Timeout(_) => "timeout",
+            CommandError::Unsupported(_) => "unsupported",
+            CommandError::Disabled => "disabled",
+        }
+    }
+
+    // Appends `detail` to the message of variants that carry one.
+    pub fn with_detail(self, detail: &str) -> Self {
+        match self {
+            CommandError::SpawnFailed(message) => {
+                CommandError::SpawnFailed(format!("{message} | {detail}"))
+            }
+            CommandError::Io(message) => CommandError::Io(format!("{message} | {detail}")),
+            CommandError::InvalidArgument(message) => {
+                CommandError::InvalidArgument(format!("{message} | {detail}"))
+            }
+            CommandError::Whisper(message) => {
+                CommandError::Whisper(format!("{message} | {detail}"))
+            }
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::SessionNotFound(id) => write!(f, "PTY session '{id}' does not exist."),
+            CommandError::SessionLimitReached(limit) => write!(
+                f,
+                "The limit of {limit} open terminal sessions was reached; close one first."
+            ),
+            CommandError::LockPoisoned => write!(f, "lock error"),
+            CommandError::SpawnFailed(message)
+            | CommandError::Io(message)
+            | CommandError::InvalidArgument(message)
+            | CommandError::Whisper(message)
+            | CommandError::Timeout(message)
+            | CommandError::Unsupported(message) => write!(f, "{message}"),
+            CommandError::Disabled => write!(f, "Speech-to-text is disabled in this build."),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+// Serialized as `{ kind, message }` so the frontend can branch on `kind` and
+// still show `message` verbatim.
+impl Serialize for CommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CommandError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(error: std::io::Error) -> Self {
+        CommandError::Io(error.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for CommandError {
+    fn from(_: PoisonError<T>) -> Self {
+        CommandError::LockPoisoned
+    }
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
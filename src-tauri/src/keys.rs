@@ -0,0 +1,207 @@
+use crate::error::{CommandError, CommandResult};
+
+#[derive(Default)]
+struct Modifiers {
+    shift: bool,
+    alt: bool,
+    ctrl: bool,
+}
+
+impl Modifiers {
+    fn parse(names: &[String]) -> CommandResult<Self> {
+        let mut modifiers = Modifiers::default();
+        for name in names {
+            match name.to_ascii_lowercase().as_str() {
+                "shift" => modifiers.shift = true,
+                "alt" | "option" | "meta" => modifiers.alt = true,
+                "ctrl" | "control" => modifiers.ctrl = true,
+                _ => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "Unknown key modifier '{name}'."
+                    )))
+                }
+            }
+        }
+        Ok(modifiers)
+    }
+
+    // xterm's modifier parameter: 1 + shift(1) + alt(2) + ctrl(4).
+    fn param(&self) -> u8 {
+        1 + u8::from(self.shift) + 2 * u8::from(self.alt) + 4 * u8::from(self.ctrl)
+    }
+
+    fn any(&self) -> bool {
+        self.shift || self.alt || self.ctrl
+    }
+}
+
+// `ESC [ <final>` keys; with modifiers they become `ESC [ 1 ; <mod> <final>`.
+fn csi_letter(final_byte: char, modifiers: &Modifiers) -> String {
+    if modifiers.any() {
+        format!("\x1b[1;{}{final_byte}", modifiers.param())
+    } else {
+        format!("\x1b[{final_byte}")
+    }
+}
+
+// `ESC [ <code> ~` keys; with modifiers they become `ESC [ <code> ; <mod> ~`.
+fn csi_tilde(code: u8, modifiers: &Modifiers) -> String {
+    if modifiers.any() {
+        format!("\x1b[{code};{}~", modifiers.param())
+    } else {
+        format!("\x1b[{code}~")
+    }
+}
+
+// F1-F4 use SS3 (`ESC O P`) unmodified and switch to CSI once modified.
+fn ss3_function(final_byte: char, modifiers: &Modifiers) -> String {
+    if modifiers.any() {
+        format!("\x1b[1;{}{final_byte}", modifiers.param())
+    } else {
+        format!("\x1bO{final_byte}")
+    }
+}
+
+fn with_alt(sequence: &str, modifiers: &Modifiers) -> String {
+    if modifiers.alt {
+        format!("\x1b{sequence}")
+    } else {
+        sequence.to_string()
+    }
+}
+
+fn encode_character(character: char, modifiers: &Modifiers) -> String {
+    let character = if modifiers.shift {
+        character.to_ascii_uppercase()
+    } else {
+        character
+    };
+    if modifiers.ctrl {
+        let control = match character.to_ascii_uppercase() {
+            upper @ ('@'..='_') => Some(upper as u8 & 0x1f),
+            ' ' => Some(0),
+            '?' => Some(0x7f),
+            _ => None,
+        };
+        if let Some(byte) = control {
+            return with_alt(&char::from(byte).to_string(), modifiers);
+        }
+    }
+    with_alt(&character.to_string(), modifiers)
+}
+
+// Encodes a DOM-style key name (`ArrowUp`, `F5`, `PageDown`, `a`, ...) plus
+// modifiers into the bytes an xterm-compatible terminal would send. Cursor
+// keys always use normal (not application) mode.
+pub fn encode_key(key: &str, modifier_names: &[String]) -> CommandResult<Vec<u8>> {
+    let modifiers = Modifiers::parse(modifier_names)?;
+    let sequence = match key {
+        "ArrowUp" | "Up" => csi_letter('A', &modifiers),
+        "ArrowDown" | "Down" => csi_letter('B', &modifiers),
+        "ArrowRight" | "Right" => csi_letter('C', &modifiers),
+        "ArrowLeft" | "Left" => csi_letter('D', &modifiers),
+        "Home" => csi_letter('H', &modifiers),
+        "End" => csi_letter('F', &modifiers),
+        "Insert" => csi_tilde(2, &modifiers),
+        "Delete" => csi_tilde(3, &modifiers),
+        "PageUp" => csi_tilde(5, &modifiers),
+        "PageDown" => csi_tilde(6, &modifiers),
+        "F1" => ss3_function('P', &modifiers),
+        "F2" => ss3_function('Q', &modifiers),
+        "F3" => ss3_function('R', &modifiers),
+        "F4" => ss3_function('S', &modifiers),
+        "F5" => csi_tilde(15, &modifiers),
+        "F6" => csi_tilde(17, &modifiers),
+        "F7" => csi_tilde(18, &modifiers),
+        "F8" => csi_tilde(19, &modifiers),
+        "F9" => csi_tilde(20, &modifiers),
+        "F10" => csi_tilde(21, &modifiers),
+        "F11" => csi_tilde(23, &modifiers),
+        "F12" => csi_tilde(24, &modifiers),
+        "Enter" => with_alt("\r", &modifiers),
+        "Tab" if modifiers.shift => "\x1b[Z".to_string(),
+        "Tab" => with_alt("\t", &modifiers),
+        "Backspace" if modifiers.ctrl => with_alt("\x08", &modifiers),
+        "Backspace" => with_alt("\x7f", &modifiers),
+        "Escape" | "Esc" => with_alt("\x1b", &modifiers),
+        "Space" => encode_character(' ', &modifiers),
+        _ => {
+            let mut characters = key.chars();
+            match (characters.next(), characters.next()) {
+                (Some(character), None) if character.is_ascii_graphic() => {
+                    encode_character(character, &modifiers)
+                }
+                _ => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "Unrecognized key name '{key}'."
+                    )))
+                }
+            }
+        }
+    };
+    Ok(sequence.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(key: &str, modifiers: &[&str]) -> String {
+        let modifiers: Vec<String> = modifiers.iter().map(|name| name.to_string()).collect();
+        String::from_utf8(encode_key(key, &modifiers).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn cursor_and_editing_keys() {
+        assert_eq!(encode("ArrowUp", &[]), "\x1b[A");
+        assert_eq!(encode("Left", &[]), "\x1b[D");
+        assert_eq!(encode("Home", &[]), "\x1b[H");
+        assert_eq!(encode("Delete", &[]), "\x1b[3~");
+        assert_eq!(encode("PageDown", &[]), "\x1b[6~");
+        assert_eq!(encode("ArrowUp", &["shift"]), "\x1b[1;2A");
+        assert_eq!(encode("ArrowRight", &["Ctrl", "Alt"]), "\x1b[1;7C");
+        assert_eq!(encode("Delete", &["control"]), "\x1b[3;5~");
+    }
+
+    #[test]
+    fn function_keys_switch_from_ss3_to_csi_when_modified() {
+        assert_eq!(encode("F1", &[]), "\x1bOP");
+        assert_eq!(encode("F4", &[]), "\x1bOS");
+        assert_eq!(encode("F1", &["shift"]), "\x1b[1;2P");
+        assert_eq!(encode("F5", &[]), "\x1b[15~");
+        assert_eq!(encode("F12", &["alt"]), "\x1b[24;3~");
+    }
+
+    #[test]
+    fn characters_and_control_keys() {
+        assert_eq!(encode("a", &[]), "a");
+        assert_eq!(encode("a", &["shift"]), "A");
+        assert_eq!(encode("c", &["ctrl"]), "\x03");
+        assert_eq!(encode("[", &["ctrl"]), "\x1b");
+        assert_eq!(encode("Space", &["ctrl"]), "\0");
+        assert_eq!(encode("?", &["ctrl"]), "\x7f");
+        assert_eq!(encode("x", &["meta"]), "\x1bx");
+        assert_eq!(encode("c", &["ctrl", "alt"]), "\x1b\x03");
+        assert_eq!(encode("Enter", &[]), "\r");
+        assert_eq!(encode("Tab", &["shift"]), "\x1b[Z");
+        assert_eq!(encode("Backspace", &[]), "\x7f");
+        assert_eq!(encode("Backspace", &["ctrl"]), "\x08");
+        assert_eq!(encode("Escape", &["alt"]), "\x1b\x1b");
+    }
+
+    #[test]
+    fn unknown_keys_and_modifiers_are_rejected() {
+        assert!(matches!(
+            encode_key("Hyper", &[]),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            encode_key("ab", &[]),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            encode_key("a", &["super".to_string()]),
+            Err(CommandError::InvalidArgument(_))
+        ));
+    }
+}
@@ -1,540 +1,6133 @@
+mod audio;
+mod config;
+mod error;
+mod keys;
+mod languages;
+mod logging;
+mod microphone;
+mod paths;
+mod screen;
+mod shells;
+mod transcript;
+
+use base64::prelude::*;
+use config::{AppConfig, ConfigState, CONFIG_FILE_NAME};
+use error::{CommandError, CommandResult};
 use portable_pty::{native_pty_system, CommandBuilder, PtySize};
-use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tauri::{Emitter, Manager};
 
 const SPEECH_TO_TEXT_DISABLED: bool = true;
 
 struct Session {
     master: Box<dyn portable_pty::MasterPty + Send>,
-    writer: Box<dyn Write + Send>,
+    // Shared so pipes can write to it without holding the sessions lock.
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
     child: Box<dyn portable_pty::Child + Send>,
+    // Root of the process tree `pty_stats` walks.
+    pid: Option<u32>,
+    env: HashMap<String, String>,
+    config: SessionConfig,
+    shared: Arc<SessionShared>,
 }
 
-struct PtyManager {
-    sessions: Mutex<HashMap<String, Session>>,
+// State touched by both the command handlers and the session's reader thread.
+#[derive(Default)]
+struct SessionShared {
+    // How often the reader thread has panicked and been replaced.
+    reader_restarts: AtomicU32,
+    // Internal subscribers to the decoded output, e.g. a waiting `pty_expect`.
+    // Senders whose receiver is gone are dropped on the next read.
+    output_watchers: Mutex<Vec<mpsc::Sender<String>>>,
+    io_log: Mutex<Option<fs::File>>,
+    // Only present for sessions created with `trackScreen`.
+    screen: Mutex<Option<screen::Screen>>,
+    // Latest size requested through `pty_resize` that hasn't been applied
+    // yet. `Some` also means a debounce thread is already waiting.
+    pending_resize: Mutex<Option<PtySize>>,
+    scrollback: Mutex<Scrollback>,
+    // `Some` while paused: output that would have been emitted collects here
+    // and goes out as one event on resume.
+    paused_output: Mutex<Option<String>>,
+    // Set for sessions created with `binary`: `pty:data` carries the raw
+    // bytes base64-encoded, and a pause buffers those bytes here instead.
+    binary: bool,
+    paused_bytes: Mutex<Vec<u8>>,
+    // Whether the child enabled focus reporting (`CSI ? 1004 h`).
+    focus_reporting: AtomicBool,
+    // `seq` of the last `pty:data` event, so listeners can spot missed ones.
+    output_seq: AtomicU64,
 }
 
-#[derive(Serialize, Clone)]
-struct PtyDataPayload {
-    id: String,
-    data: String,
+// Finds bell characters in the output stream. BEL also terminates OSC
+// sequences (shell prompts set the window title that way on every prompt),
+// so those are skipped; the state carries across reads.
+#[derive(Default)]
+struct BellScanner {
+    after_escape: bool,
+    in_osc: bool,
 }
 
-#[tauri::command]
-fn pty_create(
-    app: tauri::AppHandle,
-    state: tauri::State<PtyManager>,
-    id: String,
-    cols: u16,
-    rows: u16,
-    cwd: String,
-) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|_| "lock error")?;
-    if sessions.contains_key(&id) {
-        return Ok(());
+impl BellScanner {
+    fn count(&mut self, bytes: &[u8]) -> u64 {
+        let mut bells = 0;
+        for byte in bytes {
+            match (*byte, self.after_escape, self.in_osc) {
+                (b']', true, false) => self.in_osc = true,
+                (b'\\', true, true) | (0x07, _, true) => self.in_osc = false,
+                (0x07, _, false) => bells += 1,
+                _ => {}
+            }
+            self.after_escape = *byte == 0x1b;
+        }
+        bells
     }
+}
 
-    let pty_system = native_pty_system();
-    let pty_pair = pty_system
-        .openpty(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| e.to_string())?;
+// Finds the xterm window-size queries `CSI 14 t` (text area in pixels),
+// `CSI 18 t` (text area in characters) and `CSI 19 t` (screen in characters)
+// in the output stream so they can be answered. The state carries across
+// reads.
+#[derive(Default)]
+struct SizeQueryScanner {
+    after_escape: bool,
+    in_csi: bool,
+    // Set once the CSI has anything besides one numeric parameter.
+    unsupported: bool,
+    param: Option<u32>,
+}
 
-    #[cfg(windows)]
-    let mut cmd = CommandBuilder::new("cmd.exe");
-    #[cfg(windows)]
-    {
-        let safe_cwd = if cwd.trim().is_empty() {
-            "%USERPROFILE%".to_string()
-        } else {
-            cwd.replace('\"', "")
-        };
-        cmd.arg("/Q");
-        cmd.arg("/K");
-        cmd.arg(format!("cd /d {}", safe_cwd));
+impl SizeQueryScanner {
+    fn scan(&mut self, bytes: &[u8]) -> Vec<u32> {
+        let mut queries = Vec::new();
+        for byte in bytes {
+            if self.in_csi {
+                match *byte {
+                    digit @ b'0'..=b'9' => {
+                        let value = self.param.unwrap_or(0).saturating_mul(10);
+                        self.param = Some(value.saturating_add(u32::from(digit - b'0')));
+                    }
+                    b't' => {
+                        if let (false, Some(query @ (14 | 18 | 19))) =
+                            (self.unsupported, self.param)
+                        {
+                            queries.push(query);
+                        }
+                        self.in_csi = false;
+                    }
+                    0x40..=0x7e => self.in_csi = false,
+                    _ => self.unsupported = true,
+                }
+            } else if self.after_escape && *byte == b'[' {
+                self.in_csi = true;
+                self.unsupported = false;
+                self.param = None;
+            }
+            self.after_escape = *byte == 0x1b;
+        }
+        queries
     }
+}
 
-    #[cfg(not(windows))]
-    let mut cmd = CommandBuilder::new("/bin/bash");
-
-    let child = pty_pair
-        .slave
-        .spawn_command(cmd)
-        .map_err(|e| e.to_string())?;
-
-    let master = pty_pair.master;
-    let mut reader = master.try_clone_reader().map_err(|e| e.to_string())?;
-    let writer = master.take_writer().map_err(|e| e.to_string())?;
+// Tracks the child turning focus reporting (private mode 1004) on and off,
+// including when it is set together with other modes as in
+// `CSI ? 1004 ; 2004 h`. The state carries across reads.
+#[derive(Default)]
+struct FocusModeScanner {
+    after_escape: bool,
+    in_csi: bool,
+    private: bool,
+    params: Vec<u32>,
+}
 
-    let id_clone = id.clone();
-    let app_handle = app.clone();
-    thread::spawn(move || {
-        let mut buffer = [0u8; 8192];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(0) => break,
-                Ok(count) => {
-                    let data = String::from_utf8_lossy(&buffer[..count]).to_string();
-                    let payload = PtyDataPayload {
-                        id: id_clone.clone(),
-                        data,
-                    };
-                    let _ = app_handle.emit("pty:data", payload);
+impl FocusModeScanner {
+    // The last change to the mode within `bytes`, if any.
+    fn scan(&mut self, bytes: &[u8]) -> Option<bool> {
+        let mut change = None;
+        for byte in bytes {
+            if self.in_csi {
+                match *byte {
+                    b'?' if self.params.is_empty() => self.private = true,
+                    digit @ b'0'..=b'9' => {
+                        if self.params.is_empty() {
+                            self.params.push(0);
+                        }
+                        let param = self.params.last_mut().expect("pushed above");
+                        *param = param
+                            .saturating_mul(10)
+                            .saturating_add(u32::from(digit - b'0'));
+                    }
+                    b';' => self.params.push(0),
+                    final_byte @ (b'h' | b'l') => {
+                        if self.private && self.params.contains(&1004) {
+                            change = Some(final_byte == b'h');
+                        }
+                        self.in_csi = false;
+                    }
+                    0x40..=0x7e => self.in_csi = false,
+                    _ => {}
                 }
-                Err(_) => break,
+            } else if self.after_escape && *byte == b'[' {
+                self.in_csi = true;
+                self.private = false;
+                self.params.clear();
             }
+            self.after_escape = *byte == 0x1b;
         }
-    });
-
-    sessions.insert(
-        id,
-        Session {
-            master,
-            writer,
-            child,
-        },
-    );
+        change
+    }
+}
 
-    Ok(())
+// The xterm reply to a size query, or `None` for a pixel query while the
+// pixel size is unknown.
+fn size_report(query: u32, size: PtySize) -> Option<String> {
+    match query {
+        14 if size.pixel_width > 0 && size.pixel_height > 0 => Some(format!(
+            "\x1b[4;{};{}t",
+            size.pixel_height, size.pixel_width
+        )),
+        18 => Some(format!("\x1b[8;{};{}t", size.rows, size.cols)),
+        19 => Some(format!("\x1b[9;{};{}t", size.rows, size.cols)),
+        _ => None,
+    }
 }
 
-#[tauri::command]
-fn pty_write(state: tauri::State<PtyManager>, id: String, data: String) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|_| "lock error")?;
-    let Some(session) = sessions.get_mut(&id) else {
-        return Ok(());
+// Answers size queries the child printed, through the same writer user input
+// goes through.
+fn answer_size_queries(app: &tauri::AppHandle, id: &str, shared: &SessionShared, queries: &[u32]) {
+    let manager = app.state::<PtyManager>();
+    // A child that isn't reading its input can block the write, so it
+    // happens with the sessions lock released, as in `forward_to_pipes`.
+    let (writer, size) = {
+        let sessions = manager.sessions();
+        let Some(session) = sessions
+            .get(id)
+            .filter(|session| std::ptr::eq(Arc::as_ptr(&session.shared), shared))
+        else {
+            return;
+        };
+        (session.writer.clone(), session.config.pty_size())
     };
-    session
-        .writer
-        .write_all(data.as_bytes())
-        .map_err(|e| e.to_string())?;
-    Ok(())
+    for reply in queries.iter().filter_map(|query| size_report(*query, size)) {
+        if let Err(error) = write_input(&writer, shared, reply.as_bytes()) {
+            tracing::warn!(id, %error, "failed to answer a terminal size query");
+        }
+    }
 }
 
-#[tauri::command]
-fn pty_resize(
-    state: tauri::State<PtyManager>,
+const BELL_THROTTLE: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Clone)]
+struct PtyBellPayload {
     id: String,
-    cols: u16,
-    rows: u16,
-) -> Result<(), String> {
-    let sessions = state.sessions.lock().map_err(|_| "lock error")?;
-    let Some(session) = sessions.get(&id) else {
-        return Ok(());
-    };
-    session
-        .master
-        .resize(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| e.to_string())?;
-    Ok(())
+    // Bells rung in this session since it was spawned.
+    count: u64,
 }
 
-#[tauri::command]
-fn pty_close(state: tauri::State<PtyManager>, id: String) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|_| "lock error")?;
-    if let Some(mut session) = sessions.remove(&id) {
-        let _ = session.child.kill();
+const SCROLLBACK_LIMIT: usize = 1024 * 1024;
+
+// Decoded output kept server-side so a frontend that reattaches can replay
+// it. Whole chunks are dropped from the front once the limit is exceeded.
+#[derive(Default)]
+struct Scrollback {
+    chunks: VecDeque<String>,
+    len: usize,
+}
+
+impl Scrollback {
+    fn push(&mut self, data: &str) {
+        self.len += data.len();
+        self.chunks.push_back(data.to_string());
+        while self.len > SCROLLBACK_LIMIT && self.chunks.len() > 1 {
+            if let Some(dropped) = self.chunks.pop_front() {
+                self.len -= dropped.len();
+            }
+        }
+    }
+
+    fn contents(&self) -> String {
+        let mut contents = String::with_capacity(self.len);
+        for chunk in &self.chunks {
+            contents.push_str(chunk);
+        }
+        contents
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
     }
-    Ok(())
 }
 
-fn resolve_non_empty(value: Option<String>) -> Option<String> {
-    value.and_then(|entry| {
-        let trimmed = entry.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(trimmed.to_string())
+impl SessionShared {
+    fn log_io(&self, marker: &str, bytes: &[u8]) {
+        let mut io_log = lock_recovering(&self.io_log, "PTY I/O log");
+        let Some(file) = io_log.as_mut() else {
+            return;
+        };
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "[{}.{:03}] {marker} {}\n",
+            stamp.as_secs(),
+            stamp.subsec_millis(),
+            String::from_utf8_lossy(bytes).escape_debug()
+        );
+        if file.write_all(line.as_bytes()).is_err() {
+            *io_log = None;
         }
-    })
+    }
 }
 
-fn resolve_bundled_resource(app: &tauri::AppHandle, relative_path: &str) -> Option<String> {
-    app.path()
-        .resolve(relative_path, tauri::path::BaseDirectory::Resource)
-        .ok()
-        .filter(|resolved_path| resolved_path.exists())
-        .map(|resolved_path| resolved_path.to_string_lossy().to_string())
+#[derive(Default)]
+struct PtyManager {
+    sessions: Mutex<HashMap<String, Session>>,
+    pipes: Mutex<HashMap<String, HashSet<String>>>,
+    // Every child PID spawned for a session, keyed to the session id. Kept
+    // apart from `sessions` so processes can still be found if the two drift.
+    spawned_pids: Mutex<HashMap<u32, String>>,
+    // Kept between `pty_stats` calls because CPU usage is measured as the
+    // delta since the previous refresh.
+    system: Mutex<sysinfo::System>,
+    // Result of the last `list_shells` probe.
+    shells: Mutex<Option<Vec<shells::ShellInfo>>>,
 }
 
-fn push_unique_existing_dir(
-    seen_dirs: &mut HashSet<String>,
-    collected_dirs: &mut Vec<PathBuf>,
-    candidate: PathBuf,
-) {
-    if !candidate.exists() || !candidate.is_dir() {
-        return;
+impl Drop for PtyManager {
+    fn drop(&mut self) {
+        self.shutdown();
     }
+}
 
-    let normalized = candidate.to_string_lossy().to_string().to_lowercase();
-    if seen_dirs.insert(normalized) {
-        collected_dirs.push(candidate);
-    }
+// A panic while one of these locks is held must not brick every PTY command
+// for the rest of the app's lifetime, so poisoning is cleared and the guard
+// recovered instead of surfacing a lock error.
+fn lock_recovering<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        tracing::warn!("recovering poisoned {name} lock");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
 }
 
-fn collect_resource_search_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
-    let mut seen_dirs = HashSet::new();
-    let mut roots = Vec::new();
+impl PtyManager {
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        lock_recovering(&self.sessions, "PTY sessions")
+    }
 
-    if let Ok(resource_dir) = app.path().resource_dir() {
-        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.clone());
-        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.join("whisper"));
-        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.join("resources"));
-        push_unique_existing_dir(
-            &mut seen_dirs,
-            &mut roots,
-            resource_dir.join("resources").join("whisper"),
-        );
+    fn pipes(&self) -> MutexGuard<'_, HashMap<String, HashSet<String>>> {
+        lock_recovering(&self.pipes, "PTY pipes")
     }
 
-    if let Ok(executable_path) = std::env::current_exe() {
-        if let Some(executable_dir) = executable_path.parent() {
-            let executable_dir = executable_dir.to_path_buf();
-            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.clone());
-            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("whisper"));
-            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("resources"));
-            push_unique_existing_dir(
-                &mut seen_dirs,
-                &mut roots,
-                executable_dir.join("resources").join("whisper"),
-            );
-            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("Resources"));
-            push_unique_existing_dir(
-                &mut seen_dirs,
-                &mut roots,
-                executable_dir.join("Resources").join("whisper"),
-            );
-            if let Some(parent_dir) = executable_dir.parent() {
-                push_unique_existing_dir(&mut seen_dirs, &mut roots, parent_dir.join("resources"));
-                push_unique_existing_dir(
-                    &mut seen_dirs,
-                    &mut roots,
-                    parent_dir.join("resources").join("whisper"),
-                );
-                push_unique_existing_dir(&mut seen_dirs, &mut roots, parent_dir.join("Resources"));
-                push_unique_existing_dir(
-                    &mut seen_dirs,
-                    &mut roots,
-                    parent_dir.join("Resources").join("whisper"),
-                );
-            }
+    fn spawned_pids(&self) -> MutexGuard<'_, HashMap<u32, String>> {
+        lock_recovering(&self.spawned_pids, "PTY PID registry")
+    }
+
+    fn forget_pid(&self, session: &Session) {
+        if let Some(pid) = session.child.process_id() {
+            self.spawned_pids().remove(&pid);
         }
     }
 
-    if let Ok(current_dir) = std::env::current_dir() {
-        push_unique_existing_dir(
-            &mut seen_dirs,
-            &mut roots,
-            current_dir.join("src-tauri").join("resources"),
-        );
-        push_unique_existing_dir(
-            &mut seen_dirs,
-            &mut roots,
-            current_dir
-                .join("src-tauri")
-                .join("resources")
-                .join("whisper"),
-        );
+    // Kills every session's child so no shells outlive the app. Returns the
+    // ids of the sessions that were closed.
+    fn shutdown(&self) -> Vec<String> {
+        let drained: Vec<(String, Session)> = self.sessions().drain().collect();
+        let mut closed = Vec::with_capacity(drained.len());
+        for (id, mut session) in drained {
+            let _ = session.child.kill();
+            self.forget_pid(&session);
+            closed.push(id);
+        }
+        self.pipes().clear();
+        if !closed.is_empty() {
+            tracing::info!(count = closed.len(), "closed PTY sessions at shutdown");
+        }
+        closed
     }
 
-    roots
+    fn remove_pipes_for(&self, id: &str) {
+        let mut pipes = self.pipes();
+        pipes.remove(id);
+        for targets in pipes.values_mut() {
+            targets.remove(id);
+        }
+        pipes.retain(|_, targets| !targets.is_empty());
+    }
 }
 
-fn find_file_recursively(root: &Path, file_name: &str) -> Option<PathBuf> {
-    if !root.exists() {
-        return None;
-    }
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct PtyCreateOptions {
+    // Unix only: start the shell as a login shell so profile files are sourced.
+    // cmd.exe has no login mode, so Windows ignores this flag.
+    login: Option<bool>,
+    // Set on top of whatever is inherited, after the options below apply.
+    env: Option<HashMap<String, String>>,
+    // Start from an empty environment instead of the app's.
+    clear_env: Option<bool>,
+    // Start from an empty environment and inherit only these variables from
+    // the app, so tokens in its environment don't reach the shell.
+    env_allowlist: Option<Vec<String>>,
+    // Variables to drop from the inherited environment.
+    env_blocklist: Option<Vec<String>>,
+    shell: Option<String>,
+    // Run this program directly instead of a shell, e.g. a dedicated `htop`
+    // tab; the session ends when it exits. `shell`, `login`, `arg0` and the
+    // cmd.exe options don't apply to it.
+    program: Option<String>,
+    args: Option<Vec<String>>,
+    // Output is always emitted on `pty:data:{id}`; set to false to stop the
+    // shared `pty:data` event once every listener subscribes per session.
+    aggregate_events: Option<bool>,
+    // WHATWG encoding label for the child's output, e.g. "shift_jis" or
+    // "windows-1252". Defaults to UTF-8.
+    encoding: Option<String>,
+    // Interpret the output into a screen grid so `pty_get_screen` can return
+    // the visible text. Off by default since every byte goes through a parser.
+    track_screen: Option<bool>,
+    // Size of the text area in pixels, for programs that draw inline images
+    // (sixel, kitty graphics). Defaults to 0, meaning unknown.
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+    // cmd.exe only: run the initial `cd /d` as `@cd /d` so it isn't echoed.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    quiet_start: Option<bool>,
+    // cmd.exe only: run this command with `/C` instead of leaving an
+    // interactive prompt; the session ends when the command does.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    one_shot: Option<String>,
+    // Unix only: argv[0] for the shell, independent of its path, e.g. "-zsh"
+    // to present as a login shell.
+    #[cfg_attr(windows, allow(dead_code))]
+    arg0: Option<String>,
+    // Emit `pty:data` as base64 of the exact bytes read instead of decoded
+    // text, for binary protocols that lossy decoding would corrupt. The
+    // scrollback, screen and `pty_expect` still see decoded text.
+    binary: Option<bool>,
+}
 
-    let mut pending = vec![root.to_path_buf()];
-    while let Some(current) = pending.pop() {
-        let Ok(entries) = fs::read_dir(&current) else {
-            continue;
-        };
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                pending.push(path);
-                continue;
-            }
+fn resolve_output_encoding(label: Option<&str>) -> CommandResult<&'static encoding_rs::Encoding> {
+    let Some(label) = label.map(str::trim).filter(|label| !label.is_empty()) else {
+        return Ok(encoding_rs::UTF_8);
+    };
+    encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| CommandError::InvalidArgument(format!("Unknown output encoding '{label}'.")))
+}
 
-            let matches_name = path
-                .file_name()
-                .and_then(|candidate| candidate.to_str())
-                .map(|candidate| candidate.eq_ignore_ascii_case(file_name))
-                .unwrap_or(false);
-            if matches_name {
-                return Some(path);
-            }
-        }
-    }
+#[derive(Serialize, Clone)]
+struct PtyDataPayload {
+    id: String,
+    data: String,
+    // `data` is base64 rather than text.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    binary: bool,
+    // Starts at 1 for each spawned session and goes up by one per event.
+    seq: u64,
+}
 
-    None
+#[derive(Serialize, Clone)]
+struct PtyEventPayload {
+    id: String,
 }
 
-fn find_whisper_model_recursively(root: &Path) -> Option<PathBuf> {
-    if !root.exists() {
-        return None;
-    }
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct PtyExitPayload {
+    id: String,
+    exit_code: u32,
+}
 
-    fn model_priority(file_name: &str) -> usize {
-        match file_name {
-            "ggml-large-v3.bin" => 0,
-            "ggml-large-v3-turbo.bin" => 1,
-            "ggml-large-v2.bin" => 2,
-            "ggml-large-v1.bin" => 3,
-            "ggml-large.bin" => 4,
-            "ggml-medium.bin" => 5,
-            "ggml-medium.en.bin" => 6,
-            "ggml-small.bin" => 7,
-            "ggml-small.en.bin" => 8,
-            "ggml-base.bin" => 9,
-            "ggml-base.en.bin" => 10,
-            "ggml-tiny.bin" => 11,
-            "ggml-tiny.en.bin" => 12,
-            _ => 100,
-        }
-    }
+#[derive(Serialize, Clone)]
+struct PtyErrorPayload {
+    id: String,
+    message: String,
+    kind: String,
+    errno: Option<i32>,
+}
 
-    let mut best_match: Option<(usize, u64, PathBuf)> = None;
-    let mut pending = vec![root.to_path_buf()];
-    while let Some(current) = pending.pop() {
-        let Ok(entries) = fs::read_dir(&current) else {
-            continue;
-        };
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                pending.push(path);
-                continue;
-            }
+#[cfg(not(windows))]
+const UNIX_DEFAULT_SHELL: &str = "/bin/bash";
+#[cfg(not(windows))]
+const DEFAULT_TERM: &str = "xterm-256color";
 
-            let is_ggml_model = path
-                .file_name()
-                .and_then(|candidate| candidate.to_str())
-                .map(|candidate| {
-                    let normalized = candidate.to_ascii_lowercase();
-                    normalized.starts_with("ggml-") && normalized.ends_with(".bin")
-                })
-                .unwrap_or(false);
-            if is_ggml_model {
-                let model_name = path
-                    .file_name()
-                    .and_then(|candidate| candidate.to_str())
-                    .map(|candidate| candidate.to_ascii_lowercase())
-                    .unwrap_or_default();
-                let priority = model_priority(&model_name);
-                let size = fs::metadata(&path)
-                    .map(|metadata| metadata.len())
-                    .unwrap_or(0);
-                let should_replace = best_match
-                    .as_ref()
-                    .map(|(best_priority, best_size, _)| {
-                        priority < *best_priority
-                            || (priority == *best_priority && size > *best_size)
-                    })
-                    .unwrap_or(true);
-                if should_replace {
-                    best_match = Some((priority, size, path));
-                }
+#[cfg(not(windows))]
+fn shell_name(shell: &str) -> &str {
+    Path::new(shell)
+        .file_name()
+        .and_then(|candidate| candidate.to_str())
+        .unwrap_or(shell)
+}
+
+// bash, zsh, fish, ksh and mksh accept `-l`. Other shells (sh, dash, tcsh,
+// ...) only recognize a login through argv[0] starting with `-`, which
+// `shell_arg0` provides.
+#[cfg(not(windows))]
+fn accepts_login_flag(shell: &str) -> bool {
+    matches!(shell_name(shell), "bash" | "zsh" | "fish" | "ksh" | "mksh")
+}
+
+// Tauri event names only allow alphanumerics and `-`, `/`, `:`, `_`.
+fn emit_pty_data(
+    app: &tauri::AppHandle,
+    id: &str,
+    shared: &SessionShared,
+    data: String,
+    emit_aggregate: bool,
+) {
+    let payload = PtyDataPayload {
+        id: id.to_string(),
+        data,
+        binary: shared.binary,
+        seq: shared.output_seq.fetch_add(1, Ordering::Relaxed) + 1,
+    };
+    if emit_aggregate {
+        let _ = app.emit("pty:data", payload.clone());
+    }
+    let _ = app.emit(&session_event_name("pty:data", id), payload);
+}
+
+fn session_event_name(prefix: &str, id: &str) -> String {
+    let sanitized: String = id
+        .chars()
+        .map(|candidate| {
+            if candidate.is_ascii_alphanumeric() || matches!(candidate, '-' | '/' | ':' | '_') {
+                candidate
+            } else {
+                '_'
             }
+        })
+        .collect();
+    format!("{prefix}:{sanitized}")
+}
+
+#[derive(Clone)]
+struct SessionConfig {
+    cols: u16,
+    rows: u16,
+    pixel_width: u16,
+    pixel_height: u16,
+    cwd: String,
+    options: PtyCreateOptions,
+}
+
+impl SessionConfig {
+    fn pty_size(&self) -> PtySize {
+        PtySize {
+            rows: self.rows,
+            cols: self.cols,
+            pixel_width: self.pixel_width,
+            pixel_height: self.pixel_height,
         }
     }
+}
 
-    best_match.map(|(_, _, path)| path)
+#[cfg(windows)]
+fn build_shell_command(config: &SessionConfig) -> CommandBuilder {
+    let shell =
+        resolve_non_empty(config.options.shell.clone()).unwrap_or_else(|| "cmd.exe".to_string());
+    let is_cmd = Path::new(&shell)
+        .file_stem()
+        .and_then(|candidate| candidate.to_str())
+        .map(|candidate| candidate.eq_ignore_ascii_case("cmd"))
+        .unwrap_or(false);
+    let mut cmd = CommandBuilder::new(&shell);
+    if is_cmd {
+        let safe_cwd = if config.cwd.trim().is_empty() {
+            "%USERPROFILE%".to_string()
+        } else {
+            config.cwd.replace('\"', "")
+        };
+        let echo_prefix = if config.options.quiet_start.unwrap_or(false) {
+            "@"
+        } else {
+            ""
+        };
+        let change_dir = format!("{echo_prefix}cd /d {safe_cwd}");
+        cmd.arg("/Q");
+        match resolve_non_empty(config.options.one_shot.clone()) {
+            Some(command) => {
+                cmd.arg("/C");
+                cmd.arg(format!("{change_dir} && {command}"));
+            }
+            None => {
+                cmd.arg("/K");
+                cmd.arg(change_dir);
+            }
+        }
+    }
+    cmd
 }
 
-fn resolve_bundled_resource_candidates(
+#[cfg(not(windows))]
+fn configured_shell(config: &SessionConfig) -> String {
+    resolve_non_empty(config.options.shell.clone())
+        .unwrap_or_else(|| UNIX_DEFAULT_SHELL.to_string())
+}
+
+#[cfg(not(windows))]
+fn build_shell_command(config: &SessionConfig) -> CommandBuilder {
+    let shell = configured_shell(config);
+    let mut cmd = CommandBuilder::new(&shell);
+    if config.options.login.unwrap_or(false) && accepts_login_flag(&shell) {
+        cmd.arg("-l");
+    }
+    cmd
+}
+
+// argv[0] for a shell session when it shouldn't be the shell's path: `arg0`
+// if given (a leading '-' in it makes a login shell anyway), otherwise
+// `-name` for a login shell that doesn't take `-l`.
+#[cfg(unix)]
+fn shell_arg0(config: &SessionConfig) -> Option<String> {
+    if resolve_non_empty(config.options.program.clone()).is_some() {
+        return None;
+    }
+    resolve_non_empty(config.options.arg0.clone()).or_else(|| {
+        let shell = configured_shell(config);
+        (config.options.login.unwrap_or(false) && !accepts_login_flag(&shell))
+            .then(|| format!("-{}", shell_name(&shell)))
+    })
+}
+
+// portable_pty always passes the program path as argv[0], so a shell that
+// needs its own is started here on the terminal's slave side, with
+// `CommandExt::arg0`. The child is set up the way portable_pty sets up its
+// own: default signal handlers, a new session with the terminal as its
+// controlling tty, and no descriptors inherited beyond stdio.
+#[cfg(unix)]
+fn spawn_with_arg0(
+    master: &dyn portable_pty::MasterPty,
+    cmd: &CommandBuilder,
+    arg0: &str,
+) -> std::io::Result<Box<dyn portable_pty::Child + Send + Sync>> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+
+    let master_fd = master
+        .as_raw_fd()
+        .ok_or_else(|| std::io::Error::other("the terminal has no file descriptor"))?;
+    let slave = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(slave_tty_path(master_fd)?)?;
+    let argv = cmd.get_argv();
+    let mut command = Command::new(&argv[0]);
+    command
+        .arg0(arg0)
+        .args(&argv[1..])
+        .env_clear()
+        .envs(cmd.iter_full_env_as_str())
+        .env("SHELL", cmd.get_shell())
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    if let Some(cwd) = cmd.get_cwd() {
+        command.current_dir(cwd);
+    }
+    unsafe {
+        command.pre_exec(|| {
+            for signal in [
+                libc::SIGCHLD,
+                libc::SIGHUP,
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTERM,
+                libc::SIGALRM,
+            ] {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            portable_pty::unix::close_random_fds();
+            Ok(())
+        });
+    }
+    Ok(Box::new(command.spawn()?))
+}
+
+#[cfg(unix)]
+fn slave_tty_path(master_fd: std::os::unix::io::RawFd) -> std::io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // ptsname returns a static buffer, and sessions can be created from
+    // several threads at once.
+    static PTSNAME: Mutex<()> = Mutex::new(());
+    let _guard = lock_recovering(&PTSNAME, "ptsname");
+    let name = unsafe { libc::ptsname(master_fd) };
+    if name.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes())))
+}
+
+fn build_program_command(program: &str, args: &[String]) -> CommandResult<CommandBuilder> {
+    let Some(path) = locate_program(program) else {
+        return Err(CommandError::InvalidArgument(format!(
+            "Program '{program}' was not found (checked the path as given and PATH)."
+        )));
+    };
+    if path.is_dir() || !is_executable(&path) {
+        return Err(CommandError::InvalidArgument(format!(
+            "Program '{}' is not executable.",
+            path.display()
+        )));
+    }
+    let mut cmd = CommandBuilder::new(path);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+// Finds `program` the way the OS would: as given when it contains a path
+// separator, otherwise in each PATH directory.
+fn locate_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    let names: Vec<String> = if cfg!(windows) && path.extension().is_none() {
+        vec![format!("{program}.exe"), program.to_string()]
+    } else {
+        vec![program.to_string()]
+    };
+    // Like the OS, a matching file that can't be executed doesn't end the
+    // search.
+    std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+        names
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|candidate| candidate.is_file() && is_executable(candidate))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Context for a failed spawn: what was run, whether it exists and can be
+// executed, the cwd, and a hint for well-known OS errors.
+fn spawn_failure_details(program: &str, cwd: &str, os_error: Option<i32>) -> Vec<String> {
+    let mut details = vec![format!("shell: {program}")];
+    match locate_program(program) {
+        Some(path) if path.is_dir() => details.push(format!("'{}' is a directory", path.display())),
+        Some(path) if !is_executable(&path) => {
+            details.push(format!("'{}' exists but is not executable", path.display()))
+        }
+        Some(path) => details.push(format!("resolved to '{}'", path.display())),
+        None => details.push("not found (checked the path as given and PATH)".to_string()),
+    }
+    details.push(format!(
+        "cwd: {}",
+        if cwd.is_empty() { "(inherited)" } else { cwd }
+    ));
+    if let Some(hint) = os_error.and_then(spawn_error_hint) {
+        details.push(hint.to_string());
+    }
+    details
+}
+
+#[cfg(windows)]
+fn spawn_error_hint(code: i32) -> Option<&'static str> {
+    match code {
+        2 | 3 => Some("Windows reported ERROR_FILE_NOT_FOUND. Check the shell path."),
+        5 => Some(
+            "Windows reported ERROR_ACCESS_DENIED. Check the shell's permissions and whether antivirus software blocks it.",
+        ),
+        193 => Some(
+            "Windows reported ERROR_BAD_EXE_FORMAT. The shell isn't a valid executable for this architecture (x64 vs x86).",
+        ),
+        267 => Some("Windows reported ERROR_DIRECTORY. The working directory is invalid."),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn spawn_error_hint(code: i32) -> Option<&'static str> {
+    match code {
+        libc::ENOENT => {
+            Some("The shell, or the interpreter named in its #! line, was not found.")
+        }
+        libc::EACCES => Some(
+            "Permission denied. Make sure the shell is executable (chmod +x) and not on a noexec mount.",
+        ),
+        libc::ENOEXEC => Some(
+            "Exec format error. The shell isn't a binary for this architecture, or a script without a #! line.",
+        ),
+        _ => None,
+    }
+}
+
+fn validate_cwd(cwd: &str) -> CommandResult<()> {
+    match fs::metadata(cwd) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(CommandError::InvalidArgument(format!(
+            "Working directory '{cwd}' is not a directory."
+        ))),
+        Err(error) => Err(CommandError::InvalidArgument(format!(
+            "Working directory '{cwd}' is not accessible: {error}"
+        ))),
+    }
+}
+
+// Reads the session's output until EOF or a read error, fanning each chunk
+// out to the screen model, scrollback, watchers, events and pipes.
+fn read_pty_output(
     app: &tauri::AppHandle,
-    relative_paths: &[&str],
-    file_names: &[&str],
-) -> Option<String> {
-    for relative_path in relative_paths {
-        if let Some(path) = resolve_bundled_resource(app, relative_path) {
-            return Some(path);
+    id: &str,
+    mut reader: Box<dyn Read + Send>,
+    shared: &SessionShared,
+    encoding: &'static encoding_rs::Encoding,
+    emit_aggregate: bool,
+) {
+    let _span = tracing::info_span!("pty_reader", id = %id).entered();
+    // One decoder per reader so multi-byte sequences split across reads are
+    // stitched back together instead of turning into replacement characters.
+    let mut decoder = encoding.new_decoder();
+    let mut buffer = [0u8; 8192];
+    let mut bell_scanner = BellScanner::default();
+    let mut size_query_scanner = SizeQueryScanner::default();
+    let mut focus_mode_scanner = FocusModeScanner::default();
+    let mut bell_count = 0;
+    let mut last_bell_event: Option<Instant> = None;
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => {
+                tracing::debug!("PTY reached EOF");
+                break;
+            }
+            Ok(count) => {
+                tracing::trace!(bytes = count, "PTY output read");
+                let mut data = String::with_capacity(
+                    decoder.max_utf8_buffer_length(count).unwrap_or(count * 3),
+                );
+                let _ = decoder.decode_to_string(&buffer[..count], &mut data, false);
+                if let Some(screen) = lock_recovering(&shared.screen, "PTY screen").as_mut() {
+                    screen.feed(data.as_bytes());
+                }
+                lock_recovering(&shared.output_watchers, "PTY output watchers")
+                    .retain(|watcher| watcher.send(data.clone()).is_ok());
+                // The pause lock is held across the emit so `pty_resume`
+                // can't flush its buffer between a check and an emit, and
+                // across the scrollback push so a snapshot never holds
+                // output that isn't counted in `output_seq` yet.
+                let mut paused_output = lock_recovering(&shared.paused_output, "PTY pause");
+                lock_recovering(&shared.scrollback, "PTY scrollback").push(&data);
+                match (paused_output.as_mut(), shared.binary) {
+                    (Some(_), true) => lock_recovering(&shared.paused_bytes, "PTY pause")
+                        .extend_from_slice(&buffer[..count]),
+                    (Some(buffered), false) => buffered.push_str(&data),
+                    (None, true) => {
+                        let encoded = BASE64_STANDARD.encode(&buffer[..count]);
+                        emit_pty_data(app, id, shared, encoded, emit_aggregate)
+                    }
+                    (None, false) => emit_pty_data(app, id, shared, data, emit_aggregate),
+                }
+                drop(paused_output);
+                shared.log_io("<", &buffer[..count]);
+                let bells = bell_scanner.count(&buffer[..count]);
+                if bells > 0 {
+                    bell_count += bells;
+                    // Misbehaving programs can ring continuously; at most
+                    // one event per throttle window goes out.
+                    if !matches!(last_bell_event, Some(at) if at.elapsed() < BELL_THROTTLE) {
+                        last_bell_event = Some(Instant::now());
+                        let _ = app.emit(
+                            "pty:bell",
+                            PtyBellPayload {
+                                id: id.to_string(),
+                                count: bell_count,
+                            },
+                        );
+                    }
+                }
+                let size_queries = size_query_scanner.scan(&buffer[..count]);
+                if !size_queries.is_empty() {
+                    answer_size_queries(app, id, shared, &size_queries);
+                }
+                if let Some(enabled) = focus_mode_scanner.scan(&buffer[..count]) {
+                    shared.focus_reporting.store(enabled, Ordering::Relaxed);
+                }
+                forward_to_pipes(app, id, &buffer[..count]);
+            }
+            Err(error) => {
+                tracing::warn!(%error, "PTY read failed");
+                let _ = app.emit(
+                    "pty:error",
+                    PtyErrorPayload {
+                        id: id.to_string(),
+                        message: error.to_string(),
+                        kind: format!("{:?}", error.kind()),
+                        errno: error.raw_os_error(),
+                    },
+                );
+                break;
+            }
         }
     }
+}
 
-    for root in collect_resource_search_roots(app) {
-        for file_name in file_names {
-            if let Some(found_path) = find_file_recursively(&root, file_name) {
-                return Some(found_path.to_string_lossy().to_string());
+const MAX_READER_RESTARTS: u32 = 3;
+
+// Runs the reader on its own thread and watches it. A reader that panics is
+// replaced with a fresh clone of the master's reader (up to
+// MAX_READER_RESTARTS times); after that the session is torn down with a
+// `pty:error` instead of being left in the map producing no output.
+fn spawn_reader_supervisor(
+    app: tauri::AppHandle,
+    id: String,
+    reader: Box<dyn Read + Send>,
+    shared: Arc<SessionShared>,
+    encoding: &'static encoding_rs::Encoding,
+    emit_aggregate: bool,
+) {
+    thread::spawn(move || {
+        let mut next_reader = Some(reader);
+        while let Some(reader) = next_reader.take() {
+            let (reader_app, reader_id, reader_shared) = (app.clone(), id.clone(), shared.clone());
+            let outcome = thread::spawn(move || {
+                read_pty_output(
+                    &reader_app,
+                    &reader_id,
+                    reader,
+                    &reader_shared,
+                    encoding,
+                    emit_aggregate,
+                )
+            })
+            .join();
+            if outcome.is_ok() {
+                reap_after_eof(&app, &id, &shared);
+                break;
+            }
+
+            let restarts = shared.reader_restarts.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::error!(id = %id, restarts, "PTY reader thread panicked");
+            let manager = app.state::<PtyManager>();
+            let mut sessions = manager.sessions();
+            // The id may have been reused by `pty_restart` in the meantime.
+            let Some(session) = sessions
+                .get(&id)
+                .filter(|session| Arc::ptr_eq(&session.shared, &shared))
+            else {
+                break;
+            };
+            if restarts <= MAX_READER_RESTARTS {
+                next_reader = session.master.try_clone_reader().ok();
+            }
+            if next_reader.is_none() {
+                if let Some(mut session) = sessions.remove(&id) {
+                    let _ = session.child.kill();
+                    manager.forget_pid(&session);
+                }
+                drop(sessions);
+                manager.remove_pipes_for(&id);
+                let _ = app.emit(
+                    "pty:error",
+                    PtyErrorPayload {
+                        id: id.clone(),
+                        message: "The PTY reader stopped unexpectedly; the session was closed."
+                            .to_string(),
+                        kind: "ReaderFailed".to_string(),
+                        errno: None,
+                    },
+                );
             }
         }
+        // Wakes any `pty_expect` still waiting on this session.
+        lock_recovering(&shared.output_watchers, "PTY output watchers").clear();
+    });
+}
+
+fn spawn_session(
+    app: &tauri::AppHandle,
+    id: &str,
+    config: SessionConfig,
+) -> CommandResult<Session> {
+    // Unix shells would otherwise inherit the app's working directory; cmd.exe
+    // starts in %USERPROFILE% through its initial `cd`.
+    #[cfg(not(windows))]
+    let cwd = match config.cwd.trim() {
+        "" => paths::home_dir()
+            .ok_or_else(|| {
+                CommandError::InvalidArgument(
+                    "No working directory was given and the home directory could not be determined (HOME is not set)."
+                        .to_string(),
+                )
+            })?
+            .to_string_lossy()
+            .to_string(),
+        cwd => cwd.to_string(),
+    };
+    #[cfg(windows)]
+    let cwd = config.cwd.trim().to_string();
+    if !cwd.is_empty() {
+        validate_cwd(&cwd)?;
     }
+    let pty_system = native_pty_system();
+    let pty_pair = pty_system
+        .openpty(config.pty_size())
+        .map_err(|e| CommandError::Io(e.to_string()))?;
 
-    None
+    let encoding = resolve_output_encoding(config.options.encoding.as_deref())?;
+    let mut cmd = match resolve_non_empty(config.options.program.clone()) {
+        Some(program) => build_program_command(
+            &paths::expand_path(&program),
+            config.options.args.as_deref().unwrap_or_default(),
+        )?,
+        None => build_shell_command(&config),
+    };
+    if !cwd.is_empty() {
+        cmd.cwd(&cwd);
+    }
+
+    apply_inherited_env(&mut cmd, &config.options);
+    #[cfg_attr(windows, allow(unused_mut))]
+    let mut env = config.options.env.clone().unwrap_or_default();
+    #[cfg(not(windows))]
+    if !env.contains_key("TERM") {
+        env.insert("TERM".to_string(), DEFAULT_TERM.to_string());
+    }
+    for (key, value) in &env {
+        cmd.env(key, value);
+    }
+
+    let program = if cmd.is_default_prog() {
+        std::env::var("SHELL").unwrap_or_else(|_| "the default shell".to_string())
+    } else {
+        cmd.get_argv()
+            .first()
+            .map(|program| program.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    #[cfg(unix)]
+    let spawned = match shell_arg0(&config) {
+        Some(arg0) => spawn_with_arg0(pty_pair.master.as_ref(), &cmd, &arg0).map_err(Into::into),
+        None => pty_pair.slave.spawn_command(cmd),
+    };
+    #[cfg(not(unix))]
+    let spawned = pty_pair.slave.spawn_command(cmd);
+    let child = spawned.map_err(|e| {
+        let os_error = e
+            .downcast_ref::<std::io::Error>()
+            .and_then(std::io::Error::raw_os_error);
+        let details = spawn_failure_details(&program, &cwd, os_error);
+        tracing::error!(error = %e, program = %program, "failed to spawn PTY child");
+        CommandError::SpawnFailed(e.to_string()).with_detail(&details.join(" | "))
+    })?;
+    if let Some(pid) = child.process_id() {
+        app.state::<PtyManager>()
+            .spawned_pids()
+            .insert(pid, id.to_string());
+    }
+
+    let master = pty_pair.master;
+    let reader = master
+        .try_clone_reader()
+        .map_err(|e| CommandError::Io(e.to_string()))?;
+    let writer = master
+        .take_writer()
+        .map(|writer| Arc::new(Mutex::new(writer)))
+        .map_err(|e| CommandError::Io(e.to_string()))?;
+
+    let emit_aggregate = config.options.aggregate_events.unwrap_or(true);
+    let shared = Arc::new(SessionShared {
+        binary: config.options.binary.unwrap_or(false),
+        ..SessionShared::default()
+    });
+    if config.options.track_screen.unwrap_or(false) {
+        *lock_recovering(&shared.screen, "PTY screen") =
+            Some(screen::Screen::new(config.cols, config.rows));
+    }
+    spawn_reader_supervisor(
+        app.clone(),
+        id.to_string(),
+        reader,
+        shared.clone(),
+        encoding,
+        emit_aggregate,
+    );
+
+    let pid = child.process_id();
+    Ok(Session {
+        master,
+        writer,
+        child,
+        pid,
+        env,
+        config,
+        shared,
+    })
 }
 
-fn format_exit_status(status: &std::process::ExitStatus) -> String {
-    if let Some(code) = status.code() {
-        #[cfg(windows)]
-        {
-            let code_as_u32 = code as u32;
-            return format!("exit code {code} (0x{code_as_u32:08X})");
+// cmd.exe and PowerShell need SystemRoot to load system libraries, and
+// ComSpec and PATHEXT to find and run other commands, so these survive
+// `clearEnv` and the env lists.
+#[cfg(windows)]
+const REQUIRED_ENV: &[&str] = &["SystemRoot", "ComSpec", "PATHEXT"];
+#[cfg(not(windows))]
+const REQUIRED_ENV: &[&str] = &[];
+
+// Builds what the child inherits, in this order: `clearEnv` or an allowlist
+// empties the environment, the allowlist copies its variables back from the
+// app, then the blocklist removes variables. `options.env` goes on top.
+fn apply_inherited_env(cmd: &mut CommandBuilder, options: &PtyCreateOptions) {
+    if options.clear_env.unwrap_or(false) || options.env_allowlist.is_some() {
+        cmd.env_clear();
+        let names = options.env_allowlist.iter().flatten().map(String::as_str);
+        for name in names.chain(REQUIRED_ENV.iter().copied()) {
+            if let Some(value) = std::env::var_os(name) {
+                cmd.env(name, value);
+            }
         }
-        #[cfg(not(windows))]
+    }
+    for name in options.env_blocklist.iter().flatten() {
+        if !REQUIRED_ENV
+            .iter()
+            .any(|required| required.eq_ignore_ascii_case(name))
         {
-            return format!("exit code {code}");
+            cmd.env_remove(name);
         }
     }
+}
 
-    "terminated by signal".to_string()
+const DEFAULT_MAX_PTY_SESSIONS: usize = 64;
+
+fn max_pty_sessions(app: &tauri::AppHandle) -> usize {
+    app_config(app)
+        .max_pty_sessions
+        .or_else(|| {
+            std::env::var("GREEPY_MAX_PTY_SESSIONS")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+        })
+        .filter(|limit| *limit > 0)
+        .unwrap_or(DEFAULT_MAX_PTY_SESSIONS)
 }
 
-fn whisper_runtime_hint(status_code: Option<i32>) -> Option<&'static str> {
-    #[cfg(windows)]
-    if let Some(code) = status_code {
-        return match code as u32 {
-            0xC0000135 => Some(
-                "Windows reported STATUS_DLL_NOT_FOUND. Bundle whisper.cpp runtime DLLs (for example: whisper.dll, ggml.dll, ggml-base.dll, ggml-cpu.dll) beside whisper-cli.exe.",
-            ),
-            0xC000007B => Some(
-                "Windows reported STATUS_INVALID_IMAGE_FORMAT. Verify Whisper binary/DLL architecture matches the app (x64 vs x86).",
-            ),
-            _ => None,
-        };
+// Guards against a runaway caller exhausting PTYs and file descriptors.
+fn check_session_limit(app: &tauri::AppHandle, open: usize) -> CommandResult<()> {
+    let limit = max_pty_sessions(app);
+    if open >= limit {
+        return Err(CommandError::SessionLimitReached(limit));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PtySessionCount {
+    count: usize,
+    limit: usize,
+}
+
+#[tauri::command]
+fn pty_session_count(app: tauri::AppHandle, state: tauri::State<PtyManager>) -> PtySessionCount {
+    PtySessionCount {
+        count: state.sessions().len(),
+        limit: max_pty_sessions(&app),
     }
+}
 
-    None
+#[tauri::command]
+fn pty_create(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    cols: u16,
+    rows: u16,
+    cwd: String,
+    options: Option<PtyCreateOptions>,
+) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    if sessions.contains_key(&id) {
+        return Ok(());
+    }
+    check_session_limit(&app, sessions.len())?;
+
+    let mut options = options.unwrap_or_default();
+    if resolve_non_empty(options.shell.clone()).is_none() {
+        options.shell = app_config(&app).default_shell;
+    }
+    let config = SessionConfig {
+        cols,
+        rows,
+        pixel_width: options.pixel_width.unwrap_or(0),
+        pixel_height: options.pixel_height.unwrap_or(0),
+        cwd: paths::expand_path(cwd.trim()),
+        options,
+    };
+    let session = spawn_session(&app, &id, config)?;
+    tracing::info!(
+        id = %id,
+        pid = ?session.child.process_id(),
+        cols,
+        rows,
+        "PTY session created"
+    );
+    sessions.insert(id, session);
+
+    Ok(())
+}
+
+fn forward_to_pipes(app: &tauri::AppHandle, source_id: &str, bytes: &[u8]) {
+    let manager = app.state::<PtyManager>();
+    let destinations: Vec<String> = manager
+        .pipes()
+        .get(source_id)
+        .map(|targets| targets.iter().cloned().collect())
+        .unwrap_or_default();
+    if destinations.is_empty() {
+        return;
+    }
+
+    // Writing can block on a destination whose input is full; with the
+    // sessions lock released that can't stall every other reader.
+    let targets: Vec<_> = {
+        let sessions = manager.sessions();
+        destinations
+            .iter()
+            .filter_map(|destination| sessions.get(destination))
+            .map(|session| (session.writer.clone(), session.shared.clone()))
+            .collect()
+    };
+    for (writer, shared) in targets {
+        let _ = write_input(&writer, &shared, bytes);
+    }
+}
+
+#[tauri::command]
+fn pty_write(state: tauri::State<PtyManager>, id: String, data: String) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Ok(());
+    };
+    write_to_session(session, data.as_bytes())
+}
+
+const DEFAULT_SLOW_WRITE_DELAY: Duration = Duration::from_millis(10);
+
+static NEXT_SLOW_WRITE: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct PtyWriteDonePayload {
+    id: String,
+    handle: u64,
+    // Characters delivered before the write finished or stopped.
+    written: usize,
+    error: Option<String>,
+}
+
+// Types `data` one character at a time for devices that drop input sent in
+// bulk. Returns a handle at once; `pty:write_done` carries it when the last
+// character is out, or when the session goes away first.
+#[tauri::command]
+fn pty_write_slow(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    data: String,
+    delay_ms: Option<u64>,
+) -> CommandResult<u64> {
+    let shared = match state.sessions().get(&id) {
+        Some(session) => session.shared.clone(),
+        None => return Err(CommandError::SessionNotFound(id)),
+    };
+    let delay = delay_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_SLOW_WRITE_DELAY);
+    let handle = NEXT_SLOW_WRITE.fetch_add(1, Ordering::Relaxed);
+    thread::spawn(move || {
+        let mut written = 0;
+        let mut error = None;
+        let mut buffer = [0u8; 4];
+        for (index, character) in data.chars().enumerate() {
+            if index > 0 {
+                thread::sleep(delay);
+            }
+            let manager = app.state::<PtyManager>();
+            let mut sessions = manager.sessions();
+            // A session restarted under the same id is a different shell.
+            let Some(session) = sessions
+                .get_mut(&id)
+                .filter(|session| Arc::ptr_eq(&session.shared, &shared))
+            else {
+                error = Some(CommandError::SessionNotFound(id.clone()).to_string());
+                break;
+            };
+            if let Err(write_error) =
+                write_to_session(session, character.encode_utf8(&mut buffer).as_bytes())
+            {
+                error = Some(write_error.to_string());
+                break;
+            }
+            written += 1;
+        }
+        let _ = app.emit(
+            "pty:write_done",
+            PtyWriteDonePayload {
+                id,
+                handle,
+                written,
+                error,
+            },
+        );
+    });
+    Ok(handle)
+}
+
+fn write_to_session(session: &mut Session, bytes: &[u8]) -> CommandResult<()> {
+    write_input(&session.writer, &session.shared, bytes)
+}
+
+fn write_input(
+    writer: &Mutex<Box<dyn Write + Send>>,
+    shared: &SessionShared,
+    bytes: &[u8],
+) -> CommandResult<()> {
+    let mut writer = lock_recovering(writer, "PTY writer");
+    writer.write_all(bytes)?;
+    writer.flush()?;
+    shared.log_io(">", bytes);
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_start_io_log(
+    state: tauri::State<PtyManager>,
+    id: String,
+    path: String,
+) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let trimmed_path = path.trim();
+    if trimmed_path.is_empty() {
+        return Err(CommandError::InvalidArgument(
+            "Log file path is missing.".to_string(),
+        ));
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(trimmed_path)
+        .map_err(|error| {
+            CommandError::Io(format!("Failed to open log file '{trimmed_path}': {error}"))
+        })?;
+    *lock_recovering(&session.shared.io_log, "PTY I/O log") = Some(file);
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_stop_io_log(state: tauri::State<PtyManager>, id: String) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    *lock_recovering(&session.shared.io_log, "PTY I/O log") = None;
+    Ok(())
+}
+
+// Unlike `pty_write`, an unknown session is an error here, so a successful
+// return confirms the bytes reached the PTY master.
+#[tauri::command]
+fn pty_write_sync(
+    state: tauri::State<PtyManager>,
+    id: String,
+    data: String,
+) -> CommandResult<usize> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    write_to_session(session, data.as_bytes())?;
+    Ok(data.len())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PtyExpectMatch {
+    matched: String,
+    // All output from the write up to and including the match.
+    output: String,
+}
+
+const MAX_EXPECT_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+// expect(1)-style: writes `input`, then waits until the session's output
+// matches `pattern` (a regex, tested against everything read since the
+// write) or `timeout_ms` (at most MAX_EXPECT_TIMEOUT_MS) passes. Output is
+// tapped inside the backend, so it works regardless of which events the
+// frontend listens to.
+#[tauri::command(async)]
+fn pty_expect(
+    state: tauri::State<PtyManager>,
+    id: String,
+    input: String,
+    pattern: String,
+    timeout_ms: u64,
+) -> CommandResult<PtyExpectMatch> {
+    let pattern = regex::Regex::new(&pattern)
+        .map_err(|error| CommandError::InvalidArgument(format!("Invalid pattern: {error}")))?;
+    let (sender, receiver) = mpsc::channel();
+    {
+        let mut sessions = state.sessions();
+        let Some(session) = sessions.get_mut(&id) else {
+            return Err(CommandError::SessionNotFound(id));
+        };
+        // Subscribe before writing so a fast reply can't slip past.
+        lock_recovering(&session.shared.output_watchers, "PTY output watchers").push(sender);
+        write_to_session(session, input.as_bytes())?;
+    }
+
+    let timeout_ms = timeout_ms.min(MAX_EXPECT_TIMEOUT_MS);
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let mut output = String::new();
+    loop {
+        if let Some(found) = pattern.find(&output) {
+            let matched = found.as_str().to_string();
+            output.truncate(found.end());
+            return Ok(PtyExpectMatch { matched, output });
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(remaining) {
+            Ok(data) => output.push_str(&data),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(CommandError::Timeout(format!(
+                    "Pattern not seen in PTY session '{id}' within {timeout_ms} ms."
+                )))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(CommandError::Io(format!(
+                    "PTY session '{id}' ended before the pattern appeared."
+                )))
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PtyMode {
+    echo: bool,
+    // Line-buffered input; off means raw, byte-at-a-time input.
+    canonical: bool,
+}
+
+// On Linux and macOS the master side reports and changes the termios of the
+// slave, which is what the child's line discipline uses.
+#[cfg(unix)]
+fn read_termios(session: &Session) -> CommandResult<(i32, libc::termios)> {
+    let fd = session
+        .master
+        .as_raw_fd()
+        .ok_or_else(|| CommandError::Io("PTY master has no file descriptor.".to_string()))?;
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok((fd, unsafe { termios.assume_init() }))
+}
+
+#[cfg(unix)]
+fn line_mode(session: &Session) -> CommandResult<PtyMode> {
+    let (_, termios) = read_termios(session)?;
+    Ok(PtyMode {
+        echo: termios.c_lflag & libc::ECHO != 0,
+        canonical: termios.c_lflag & libc::ICANON != 0,
+    })
+}
+
+#[cfg(unix)]
+fn set_line_mode(session: &Session, echo: Option<bool>, raw: Option<bool>) -> CommandResult<()> {
+    let (fd, mut termios) = read_termios(session)?;
+    let mut set_flag = |flag: libc::tcflag_t, on: bool| {
+        if on {
+            termios.c_lflag |= flag;
+        } else {
+            termios.c_lflag &= !flag;
+        }
+    };
+    if let Some(echo) = echo {
+        set_flag(libc::ECHO, echo);
+    }
+    if let Some(raw) = raw {
+        set_flag(libc::ICANON, !raw);
+    }
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+// ConPTY doesn't expose the child's console mode to the host.
+#[cfg(windows)]
+fn line_mode(_session: &Session) -> CommandResult<PtyMode> {
+    Err(CommandError::Unsupported(
+        "Line discipline flags are not available for Windows console sessions.".to_string(),
+    ))
+}
+
+#[cfg(windows)]
+fn set_line_mode(session: &Session, _echo: Option<bool>, _raw: Option<bool>) -> CommandResult<()> {
+    line_mode(session).map(|_| ())
+}
+
+#[tauri::command]
+fn pty_get_mode(state: tauri::State<PtyManager>, id: String) -> CommandResult<PtyMode> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    line_mode(session)
+}
+
+// Toggles echo and raw (non-canonical) input; omitted flags are left alone.
+// Returns the resulting mode.
+#[tauri::command]
+fn pty_set_mode(
+    state: tauri::State<PtyManager>,
+    id: String,
+    echo: Option<bool>,
+    raw: Option<bool>,
+) -> CommandResult<PtyMode> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    set_line_mode(session, echo, raw)?;
+    line_mode(session)
+}
+
+// Sends the `CSI 8 ; rows ; cols t` size report to the child on demand, for
+// programs that asked through a channel the automatic replies don't cover.
+#[tauri::command]
+fn pty_report_size(state: tauri::State<PtyManager>, id: String) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let report = size_report(18, session.config.pty_size()).unwrap_or_default();
+    write_to_session(session, report.as_bytes())
+}
+
+// Tells the child the terminal window gained (`CSI I`) or lost (`CSI O`)
+// focus. Only sent while the child has focus reporting enabled; returns
+// whether anything was written.
+#[tauri::command]
+fn pty_set_focus(
+    state: tauri::State<PtyManager>,
+    id: String,
+    focused: bool,
+) -> CommandResult<bool> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    if !session.shared.focus_reporting.load(Ordering::Relaxed) {
+        return Ok(false);
+    }
+    let sequence: &[u8] = if focused { b"\x1b[I" } else { b"\x1b[O" };
+    write_to_session(session, sequence)?;
+    Ok(true)
+}
+
+// Writes the same input to each listed session. Like `pty_resize_all`, every
+// session is attempted and the returned map holds the failures by id.
+#[tauri::command]
+fn pty_broadcast(
+    state: tauri::State<PtyManager>,
+    ids: Vec<String>,
+    data: String,
+) -> HashMap<String, String> {
+    let mut sessions = state.sessions();
+    let mut failures = HashMap::new();
+    for id in ids {
+        let result = match sessions.get_mut(&id) {
+            Some(session) => write_to_session(session, data.as_bytes()),
+            None => Err(CommandError::SessionNotFound(id.clone())),
+        };
+        if let Err(error) = result {
+            failures.insert(id, error.to_string());
+        }
+    }
+    failures
+}
+
+#[tauri::command]
+fn pty_send_key(
+    state: tauri::State<PtyManager>,
+    id: String,
+    key: String,
+    modifiers: Option<Vec<String>>,
+) -> CommandResult<()> {
+    let sequence = keys::encode_key(&key, &modifiers.unwrap_or_default())?;
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    write_to_session(session, &sequence)
+}
+
+#[tauri::command]
+fn pty_get_scrollback(state: tauri::State<PtyManager>, id: String) -> CommandResult<String> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let scrollback = lock_recovering(&session.shared.scrollback, "PTY scrollback");
+    Ok(scrollback.contents())
+}
+
+#[derive(Serialize)]
+struct PtyScrollbackSnapshot {
+    data: String,
+    seq: u64,
+}
+
+// The scrollback together with the `seq` of the last `pty:data` event it
+// covers, for resyncing after missed events: replace the screen with `data`
+// and drop incoming events up to `seq`. While paused, `data` also holds the
+// buffered output that will arrive with the event after `seq`.
+#[tauri::command]
+fn pty_get_scrollback_snapshot(
+    state: tauri::State<PtyManager>,
+    id: String,
+) -> CommandResult<PtyScrollbackSnapshot> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    // Same lock order as the reader, which pushes and emits under the pause
+    // lock.
+    let _paused_output = lock_recovering(&session.shared.paused_output, "PTY pause");
+    let data = lock_recovering(&session.shared.scrollback, "PTY scrollback").contents();
+    Ok(PtyScrollbackSnapshot {
+        data,
+        seq: session.shared.output_seq.load(Ordering::Relaxed),
+    })
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum ScrollbackFormat {
+    // Escape sequences stripped.
+    Text,
+    // Exactly what the program printed.
+    Ansi,
+    // A standalone page with colors and attributes as styled spans.
+    Html,
+}
+
+// Saves the session's scrollback to `path` for sharing. Returns the path that
+// was written, with `~` and variables expanded.
+#[tauri::command]
+fn pty_export_scrollback(
+    state: tauri::State<PtyManager>,
+    id: String,
+    path: String,
+    format: ScrollbackFormat,
+) -> CommandResult<String> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let contents = lock_recovering(&session.shared.scrollback, "PTY scrollback").contents();
+    drop(sessions);
+
+    let Some(path) = resolve_user_path(Some(path)) else {
+        return Err(CommandError::InvalidArgument(
+            "An export path is required.".to_string(),
+        ));
+    };
+    let exported = match format {
+        ScrollbackFormat::Text => screen::strip_ansi(&contents),
+        ScrollbackFormat::Ansi => contents,
+        ScrollbackFormat::Html => screen::ansi_to_html(&contents),
+    };
+    fs::write(&path, exported).map_err(|error| {
+        CommandError::Io(format!("Failed to write scrollback to '{path}': {error}"))
+    })?;
+    Ok(path)
+}
+
+// Last `lines` logical lines of the scrollback, fewer if it holds fewer.
+// Escape sequences are stripped unless `strip_ansi` is false.
+#[tauri::command]
+fn pty_tail(
+    state: tauri::State<PtyManager>,
+    id: String,
+    lines: usize,
+    strip_ansi: Option<bool>,
+) -> CommandResult<Vec<String>> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let contents = lock_recovering(&session.shared.scrollback, "PTY scrollback").contents();
+    drop(sessions);
+
+    let text = if strip_ansi.unwrap_or(true) {
+        screen::strip_ansi(&contents)
+    } else {
+        contents
+    };
+    let all_lines: Vec<&str> = text
+        .strip_suffix('\n')
+        .unwrap_or(&text)
+        .split('\n')
+        .collect();
+    let start = all_lines.len().saturating_sub(lines);
+    Ok(all_lines[start..]
+        .iter()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect())
+}
+
+// Wipes the server-side history and sends the shell a form feed (Ctrl+L) so
+// it clears the live view and redraws its prompt; `pty:cleared` tells the
+// frontend to drop its own buffer as well.
+#[tauri::command]
+fn pty_clear(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    lock_recovering(&session.shared.scrollback, "PTY scrollback").clear();
+    if let Some(screen) = lock_recovering(&session.shared.screen, "PTY screen").as_mut() {
+        *screen = screen::Screen::new(session.config.cols, session.config.rows);
+    }
+    write_to_session(session, b"\x0c")?;
+    drop(sessions);
+
+    let _ = app.emit("pty:cleared", PtyEventPayload { id });
+    Ok(())
+}
+
+// Stops `pty:data` events for a session while the reader keeps draining the
+// PTY (into the scrollback and the pause buffer), so the child never blocks
+// on a full PTY buffer.
+#[tauri::command]
+fn pty_pause(state: tauri::State<PtyManager>, id: String) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    lock_recovering(&session.shared.paused_output, "PTY pause").get_or_insert_with(String::new);
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_resume(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let emit_aggregate = session.config.options.aggregate_events.unwrap_or(true);
+    let shared = session.shared.clone();
+    drop(sessions);
+
+    let mut paused_output = lock_recovering(&shared.paused_output, "PTY pause");
+    if shared.binary && paused_output.take().is_some() {
+        let buffered = std::mem::take(&mut *lock_recovering(&shared.paused_bytes, "PTY pause"));
+        if !buffered.is_empty() {
+            emit_pty_data(
+                &app,
+                &id,
+                &shared,
+                BASE64_STANDARD.encode(buffered),
+                emit_aggregate,
+            );
+        }
+    } else if let Some(buffered) = paused_output.take().filter(|buffered| !buffered.is_empty()) {
+        emit_pty_data(&app, &id, &shared, buffered, emit_aggregate);
+    }
+    Ok(())
+}
+
+// DECSTR keeps the screen contents; RIS clears everything, scrollback in the
+// emulator included. Both end with sgr0 for emulators that skip attributes.
+const SOFT_RESET_SEQUENCE: &str = "\x1b[!p\x1b[0m";
+const HARD_RESET_SEQUENCE: &str = "\x1bc\x1b[0m";
+
+#[derive(Serialize, Clone)]
+struct PtyResetPayload {
+    id: String,
+    hard: bool,
+}
+
+// Output that didn't come from the child, taking the same route as what the
+// reader thread emits so the screen model, scrollback and a pause all see it.
+fn emit_local_output(
+    app: &tauri::AppHandle,
+    id: &str,
+    shared: &SessionShared,
+    data: &str,
+    emit_aggregate: bool,
+) {
+    if let Some(screen) = lock_recovering(&shared.screen, "PTY screen").as_mut() {
+        screen.feed(data.as_bytes());
+    }
+    let mut paused_output = lock_recovering(&shared.paused_output, "PTY pause");
+    lock_recovering(&shared.scrollback, "PTY scrollback").push(data);
+    match (paused_output.as_mut(), shared.binary) {
+        (Some(_), true) => {
+            lock_recovering(&shared.paused_bytes, "PTY pause").extend_from_slice(data.as_bytes())
+        }
+        (Some(buffered), false) => buffered.push_str(data),
+        (None, true) => emit_pty_data(
+            app,
+            id,
+            shared,
+            BASE64_STANDARD.encode(data),
+            emit_aggregate,
+        ),
+        (None, false) => emit_pty_data(app, id, shared, data.to_string(), emit_aggregate),
+    }
+}
+
+// Turns echo, canonical input, signals and output newline translation back
+// on, like the termios half of `stty sane`.
+#[cfg(unix)]
+fn restore_sane_termios(session: &Session) -> CommandResult<()> {
+    let Some(fd) = session.master.as_raw_fd() else {
+        return Ok(());
+    };
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut termios = unsafe { termios.assume_init() };
+    termios.c_lflag |=
+        libc::ECHO | libc::ECHOE | libc::ECHOK | libc::ICANON | libc::ISIG | libc::IEXTEN;
+    termios.c_iflag |= libc::ICRNL | libc::IXON;
+    termios.c_iflag &= !(libc::INLCR | libc::IGNCR);
+    termios.c_oflag |= libc::OPOST | libc::ONLCR;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+// ConPTY has no termios; the console restores its own input modes.
+#[cfg(windows)]
+fn restore_sane_termios(_session: &Session) -> CommandResult<()> {
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_reset(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    hard: bool,
+    restore_echo: Option<bool>,
+) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    if restore_echo.unwrap_or(hard) {
+        restore_sane_termios(session)?;
+    }
+    let emit_aggregate = session.config.options.aggregate_events.unwrap_or(true);
+    let shared = session.shared.clone();
+    drop(sessions);
+
+    if hard {
+        shared.focus_reporting.store(false, Ordering::Relaxed);
+    }
+    let sequence = if hard {
+        HARD_RESET_SEQUENCE
+    } else {
+        SOFT_RESET_SEQUENCE
+    };
+    emit_local_output(&app, &id, &shared, sequence, emit_aggregate);
+    let _ = app.emit("pty:reset", PtyResetPayload { id, hard });
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_get_screen(state: tauri::State<PtyManager>, id: String) -> CommandResult<Vec<String>> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let screen = lock_recovering(&session.shared.screen, "PTY screen");
+    screen.as_ref().map(screen::Screen::lines).ok_or_else(|| {
+        CommandError::InvalidArgument(format!(
+            "Screen tracking is not enabled for PTY session '{id}'; create it with `trackScreen: true`."
+        ))
+    })
+}
+
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+// Dragging a window edge produces a burst of resizes; only the last size in
+// each debounce window reaches the PTY, so full-screen apps redraw once.
+// Failures surface as `pty:error` since the command has already returned.
+#[tauri::command]
+fn pty_resize(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    cols: u16,
+    rows: u16,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> CommandResult<()> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Ok(());
+    };
+    let shared = session.shared.clone();
+    drop(sessions);
+
+    let already_scheduled = lock_recovering(&shared.pending_resize, "PTY resize")
+        .replace(PtySize {
+            rows,
+            cols,
+            pixel_width: pixel_width.unwrap_or(0),
+            pixel_height: pixel_height.unwrap_or(0),
+        })
+        .is_some();
+    if already_scheduled {
+        return Ok(());
+    }
+    thread::spawn(move || {
+        thread::sleep(RESIZE_DEBOUNCE);
+        let Some(size) = lock_recovering(&shared.pending_resize, "PTY resize").take() else {
+            return;
+        };
+        let manager = app.state::<PtyManager>();
+        let mut sessions = manager.sessions();
+        // The session may have been closed or restarted in the meantime.
+        let Some(session) = sessions
+            .get_mut(&id)
+            .filter(|session| Arc::ptr_eq(&session.shared, &shared))
+        else {
+            return;
+        };
+        if let Err(error) = resize_session(session, size) {
+            drop(sessions);
+            let _ = app.emit(
+                "pty:error",
+                PtyErrorPayload {
+                    id,
+                    message: error.to_string(),
+                    kind: error.kind().to_string(),
+                    errno: None,
+                },
+            );
+        }
+    });
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PtyGridSize {
+    cols: u16,
+    rows: u16,
+}
+
+// `pty_resize` for frontends that measure the terminal in pixels: the grid is
+// as many whole cells as fit (at least one each way) and the pixel size is
+// passed along with it. Returns the grid that was requested.
+#[tauri::command]
+fn pty_resize_pixels(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    pixel_width: u16,
+    pixel_height: u16,
+    cell_width: u16,
+    cell_height: u16,
+) -> CommandResult<PtyGridSize> {
+    if cell_width == 0 || cell_height == 0 {
+        return Err(CommandError::InvalidArgument(format!(
+            "Cell size must be non-zero (got {cell_width}x{cell_height})."
+        )));
+    }
+    let grid = PtyGridSize {
+        cols: (pixel_width / cell_width).max(1),
+        rows: (pixel_height / cell_height).max(1),
+    };
+    pty_resize(
+        app,
+        state,
+        id,
+        grid.cols,
+        grid.rows,
+        Some(pixel_width),
+        Some(pixel_height),
+    )?;
+    Ok(grid)
+}
+
+// The kernel sends SIGWINCH to the terminal's foreground process group when
+// the window size changes, but only if it actually changed, and programs that
+// start their own process group (some pagers and multiplexers) have been seen
+// to miss it. Signalling the current foreground group again is harmless and
+// makes full-screen programs redraw reliably.
+#[cfg(unix)]
+fn signal_foreground_resize(session: &Session) {
+    let Some(fd) = session.master.as_raw_fd() else {
+        return;
+    };
+    let group = unsafe { libc::tcgetpgrp(fd) };
+    if group > 0 {
+        unsafe { libc::killpg(group, libc::SIGWINCH) };
+    }
+}
+
+fn resize_session(session: &mut Session, size: PtySize) -> CommandResult<()> {
+    session
+        .master
+        .resize(size)
+        .map_err(|e| CommandError::Io(e.to_string()))?;
+    #[cfg(unix)]
+    signal_foreground_resize(session);
+    if let Some(screen) = lock_recovering(&session.shared.screen, "PTY screen").as_mut() {
+        screen.resize(size.cols, size.rows);
+    }
+    // Keep the stored config current so restarts reuse the latest size.
+    session.config.cols = size.cols;
+    session.config.rows = size.rows;
+    session.config.pixel_width = size.pixel_width;
+    session.config.pixel_height = size.pixel_height;
+    Ok(())
+}
+
+// Applies to every session even if some fail; the returned map holds the
+// error for each session that could not be resized.
+#[tauri::command]
+fn pty_resize_all(
+    state: tauri::State<PtyManager>,
+    cols: u16,
+    rows: u16,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> HashMap<String, String> {
+    let size = PtySize {
+        rows,
+        cols,
+        pixel_width: pixel_width.unwrap_or(0),
+        pixel_height: pixel_height.unwrap_or(0),
+    };
+    let mut sessions = state.sessions();
+    sessions
+        .iter_mut()
+        .filter_map(|(id, session)| {
+            resize_session(session, size)
+                .err()
+                .map(|error| (id.clone(), error.to_string()))
+        })
+        .collect()
+}
+
+// Installed shells for a shell picker, from /etc/shells on Unix and the usual
+// install locations on Windows. Probed once; pass `refresh` to probe again.
+#[tauri::command]
+fn list_shells(state: tauri::State<PtyManager>, refresh: Option<bool>) -> Vec<shells::ShellInfo> {
+    let mut cached = lock_recovering(&state.shells, "shell list");
+    if refresh.unwrap_or(false) {
+        *cached = None;
+    }
+    cached.get_or_insert_with(shells::detect_shells).clone()
+}
+
+#[tauri::command]
+fn pty_get_env(
+    state: tauri::State<PtyManager>,
+    id: String,
+) -> CommandResult<HashMap<String, String>> {
+    let sessions = state.sessions();
+    let Some(session) = sessions.get(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    Ok(session.env.clone())
+}
+
+#[tauri::command]
+fn pty_close(state: tauri::State<PtyManager>, id: String) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    if let Some(mut session) = sessions.remove(&id) {
+        let _ = session.child.kill();
+        state.forget_pid(&session);
+    }
+    drop(sessions);
+
+    state.remove_pipes_for(&id);
+    Ok(())
+}
+
+// Closes every session, e.g. from the frontend's window-close handler. The
+// same cleanup also runs when the app exits. Returns the closed ids.
+#[tauri::command]
+fn pty_shutdown(state: tauri::State<PtyManager>) -> Vec<String> {
+    state.shutdown()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PtyStats {
+    // Summed over the tree, so it can exceed 100 on multi-core machines.
+    cpu_percent: f32,
+    memory_bytes: u64,
+    process_count: usize,
+}
+
+// CPU and resident memory of a session's child and all of its descendants.
+// The first call for a process reports 0% CPU since there is no earlier
+// sample. Returns `None` when the child's PID is unknown or already gone.
+#[tauri::command]
+fn pty_stats(state: tauri::State<PtyManager>, id: String) -> CommandResult<Option<PtyStats>> {
+    let Some(root) = state.sessions().get(&id).map(|session| session.pid) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let Some(root) = root.map(sysinfo::Pid::from_u32) else {
+        return Ok(None);
+    };
+    let mut system = lock_recovering(&state.system, "process stats");
+    system.refresh_processes();
+    let processes = system.processes();
+    if !processes.contains_key(&root) {
+        return Ok(None);
+    }
+    let mut children: HashMap<sysinfo::Pid, Vec<sysinfo::Pid>> = HashMap::new();
+    for (pid, process) in processes {
+        if let Some(parent) = process.parent() {
+            children.entry(parent).or_default().push(*pid);
+        }
+    }
+    let mut stats = PtyStats {
+        cpu_percent: 0.0,
+        memory_bytes: 0,
+        process_count: 0,
+    };
+    let mut pending = vec![root];
+    let mut seen = HashSet::new();
+    while let Some(pid) = pending.pop() {
+        if !seen.insert(pid) {
+            continue;
+        }
+        if let Some(process) = processes.get(&pid) {
+            stats.cpu_percent += process.cpu_usage();
+            stats.memory_bytes += process.memory();
+            stats.process_count += 1;
+        }
+        if let Some(descendants) = children.get(&pid) {
+            pending.extend(descendants);
+        }
+    }
+    Ok(Some(stats))
+}
+
+#[tauri::command]
+fn pty_is_alive(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+    cleanup: Option<bool>,
+) -> CommandResult<bool> {
+    let mut sessions = state.sessions();
+    let Some(session) = sessions.get_mut(&id) else {
+        return Ok(false);
+    };
+    if !cleanup.unwrap_or(false) {
+        return Ok(matches!(session.child.try_wait(), Ok(None)));
+    }
+    // Clean up the same way the reaper does, so the frontend still gets
+    // `pty:exit` for a session it removed this way.
+    if let Some(exit_code) = take_exited_session(&state, &mut sessions, &id) {
+        drop(sessions);
+        announce_session_exit(&app, id, exit_code);
+        return Ok(false);
+    }
+    Ok(sessions
+        .get_mut(&id)
+        .is_some_and(|session| matches!(session.child.try_wait(), Ok(None))))
+}
+
+#[tauri::command]
+fn pty_restart(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    id: String,
+) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    let Some(mut previous) = sessions.remove(&id) else {
+        return Err(CommandError::SessionNotFound(id));
+    };
+    let _ = previous.child.kill();
+    state.forget_pid(&previous);
+    let config = previous.config.clone();
+    drop(previous);
+
+    let session = spawn_session(&app, &id, config)?;
+    sessions.insert(id.clone(), session);
+    drop(sessions);
+
+    let _ = app.emit("pty:restarted", PtyEventPayload { id });
+    Ok(())
+}
+
+// Starts a fresh process with the source session's stored spawn config. The
+// cwd is the one the source was created with, not wherever its shell is now.
+#[tauri::command]
+fn pty_clone(
+    app: tauri::AppHandle,
+    state: tauri::State<PtyManager>,
+    source_id: String,
+    new_id: String,
+) -> CommandResult<()> {
+    let mut sessions = state.sessions();
+    if sessions.contains_key(&new_id) {
+        return Err(CommandError::InvalidArgument(format!(
+            "PTY session '{new_id}' already exists."
+        )));
+    }
+    let Some(source) = sessions.get(&source_id) else {
+        return Err(CommandError::SessionNotFound(source_id));
+    };
+    check_session_limit(&app, sessions.len())?;
+    let config = source.config.clone();
+
+    let session = spawn_session(&app, &new_id, config)?;
+    sessions.insert(new_id, session);
+    Ok(())
+}
+
+const DEFAULT_REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+// `GREEPY_PTY_REAP_INTERVAL_MS=0` turns the reaper off.
+fn reap_interval() -> Option<Duration> {
+    match std::env::var("GREEPY_PTY_REAP_INTERVAL_MS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+    {
+        Some(0) => None,
+        Some(ms) => Some(Duration::from_millis(ms)),
+        None => Some(DEFAULT_REAP_INTERVAL),
+    }
+}
+
+// Removes the session if its child has exited and returns the exit code. The
+// check and the removal happen under the caller's lock, so a concurrent
+// `pty_close` either removes the session first or not at all. Follow up with
+// `announce_session_exit` once the lock is released.
+fn take_exited_session(
+    state: &PtyManager,
+    sessions: &mut HashMap<String, Session>,
+    id: &str,
+) -> Option<u32> {
+    let exit_code = match sessions.get_mut(id)?.child.try_wait() {
+        Ok(Some(status)) => status.exit_code(),
+        _ => return None,
+    };
+    if let Some(session) = sessions.remove(id) {
+        state.forget_pid(&session);
+    }
+    Some(exit_code)
+}
+
+fn announce_session_exit(app: &tauri::AppHandle, id: String, exit_code: u32) {
+    app.state::<PtyManager>().remove_pipes_for(&id);
+    let _ = app.emit("pty:exit", PtyExitPayload { id, exit_code });
+}
+
+// Drops sessions whose child has exited, as a safety net for frontends that
+// never call `pty_close`.
+fn reap_exited_sessions(app: &tauri::AppHandle) {
+    let state = app.state::<PtyManager>();
+    let mut sessions = state.sessions();
+    let ids: Vec<String> = sessions.keys().cloned().collect();
+    let exited: Vec<(String, u32)> = ids
+        .into_iter()
+        .filter_map(|id| {
+            let exit_code = take_exited_session(&state, &mut sessions, &id)?;
+            Some((id, exit_code))
+        })
+        .collect();
+    drop(sessions);
+
+    for (id, exit_code) in exited {
+        announce_session_exit(app, id, exit_code);
+    }
+}
+
+const EOF_REAP_ATTEMPTS: u32 = 50;
+const EOF_REAP_INTERVAL: Duration = Duration::from_millis(20);
+
+// The reader reaches EOF once the child, and anything else holding the PTY,
+// is gone; the child may just not be reaped yet. Cleaning up here means
+// `pty:exit` doesn't wait for the reaper's next pass, or never come when the
+// reaper is disabled. A child that outlives the wait is left to the reaper.
+fn reap_after_eof(app: &tauri::AppHandle, id: &str, shared: &Arc<SessionShared>) {
+    let manager = app.state::<PtyManager>();
+    for _ in 0..EOF_REAP_ATTEMPTS {
+        let mut sessions = manager.sessions();
+        // Closed, or replaced by `pty_restart` under the same id.
+        if !sessions
+            .get(id)
+            .is_some_and(|session| Arc::ptr_eq(&session.shared, shared))
+        {
+            return;
+        }
+        if let Some(exit_code) = take_exited_session(&manager, &mut sessions, id) {
+            drop(sessions);
+            announce_session_exit(app, id.to_string(), exit_code);
+            return;
+        }
+        drop(sessions);
+        thread::sleep(EOF_REAP_INTERVAL);
+    }
+}
+
+#[cfg(unix)]
+fn kill_pid(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process still exists.
+    unsafe { libc::kill(pid, 0) == 0 && libc::kill(pid, libc::SIGKILL) == 0 }
+}
+
+#[cfg(windows)]
+fn kill_pid(pid: u32) -> bool {
+    let mut command = Command::new("taskkill");
+    command.args(["/PID", &pid.to_string(), "/T", "/F"]);
+    hide_console_window(&mut command);
+    command
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+// Kills registered PTY children that no longer belong to a session in the
+// map, e.g. after a panic left the two out of sync. Returns the killed PIDs.
+#[tauri::command]
+fn kill_orphans(state: tauri::State<PtyManager>) -> Vec<u32> {
+    let active: HashSet<u32> = state
+        .sessions()
+        .values()
+        .filter_map(|session| session.child.process_id())
+        .collect();
+    let mut spawned_pids = state.spawned_pids();
+    let orphans: Vec<u32> = spawned_pids
+        .keys()
+        .filter(|pid| !active.contains(pid))
+        .copied()
+        .collect();
+    let mut killed = Vec::new();
+    for pid in orphans {
+        spawned_pids.remove(&pid);
+        if kill_pid(pid) {
+            killed.push(pid);
+        }
+    }
+    killed
+}
+
+#[tauri::command]
+fn pty_pipe(
+    state: tauri::State<PtyManager>,
+    source_id: String,
+    dest_id: String,
+) -> CommandResult<()> {
+    if source_id == dest_id {
+        return Err(CommandError::InvalidArgument(
+            "A PTY session cannot be piped into itself.".to_string(),
+        ));
+    }
+    let sessions = state.sessions();
+    for id in [&source_id, &dest_id] {
+        if !sessions.contains_key(id) {
+            return Err(CommandError::SessionNotFound(id.clone()));
+        }
+    }
+    drop(sessions);
+
+    let mut pipes = state.pipes();
+    // A cycle would echo output around forever, and two sessions blocked on
+    // each other's full input would never drain.
+    let mut pending = vec![dest_id.as_str()];
+    let mut seen = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if id == source_id {
+            return Err(CommandError::InvalidArgument(format!(
+                "Piping '{source_id}' into '{dest_id}' would create a cycle."
+            )));
+        }
+        if seen.insert(id) {
+            pending.extend(pipes.get(id).into_iter().flatten().map(String::as_str));
+        }
+    }
+    pipes.entry(source_id).or_default().insert(dest_id);
+    Ok(())
+}
+
+#[tauri::command]
+fn pty_unpipe(
+    state: tauri::State<PtyManager>,
+    source_id: String,
+    dest_id: String,
+) -> CommandResult<()> {
+    let mut pipes = state.pipes();
+    if let Some(targets) = pipes.get_mut(&source_id) {
+        targets.remove(&dest_id);
+        if targets.is_empty() {
+            pipes.remove(&source_id);
+        }
+    }
+    Ok(())
+}
+
+fn app_config(app: &tauri::AppHandle) -> AppConfig {
+    app.try_state::<ConfigState>()
+        .map(|state| state.get())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+fn get_config(state: tauri::State<ConfigState>) -> AppConfig {
+    state.get()
+}
+
+#[tauri::command]
+fn set_config(state: tauri::State<ConfigState>, config: AppConfig) -> CommandResult<()> {
+    state.set(config)
+}
+
+// Like `resolve_non_empty`, with `~` and environment variables expanded.
+fn resolve_user_path(value: Option<String>) -> Option<String> {
+    resolve_non_empty(value).map(|path| paths::expand_path(&path))
+}
+
+fn resolve_non_empty(value: Option<String>) -> Option<String> {
+    value.and_then(|entry| {
+        let trimmed = entry.trim();
+        if trimmed.is_empty() {
+            None
+        } else {
+            Some(trimmed.to_string())
+        }
+    })
+}
+
+fn resolve_bundled_resource(app: &tauri::AppHandle, relative_path: &str) -> Option<String> {
+    app.path()
+        .resolve(relative_path, tauri::path::BaseDirectory::Resource)
+        .ok()
+        .filter(|resolved_path| resolved_path.exists())
+        .map(|resolved_path| resolved_path.to_string_lossy().to_string())
+}
+
+fn push_unique_existing_dir(
+    seen_dirs: &mut HashSet<String>,
+    collected_dirs: &mut Vec<PathBuf>,
+    candidate: PathBuf,
+) {
+    if !candidate.exists() || !candidate.is_dir() {
+        return;
+    }
+
+    let normalized = candidate.to_string_lossy().to_string().to_lowercase();
+    if seen_dirs.insert(normalized) {
+        collected_dirs.push(candidate);
+    }
+}
+
+fn collect_resource_search_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut seen_dirs = HashSet::new();
+    let mut roots = Vec::new();
+
+    if let Ok(resource_dir) = app.path().resource_dir() {
+        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.clone());
+        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.join("whisper"));
+        push_unique_existing_dir(&mut seen_dirs, &mut roots, resource_dir.join("resources"));
+        push_unique_existing_dir(
+            &mut seen_dirs,
+            &mut roots,
+            resource_dir.join("resources").join("whisper"),
+        );
+    }
+
+    if let Ok(executable_path) = std::env::current_exe() {
+        if let Some(executable_dir) = executable_path.parent() {
+            let executable_dir = executable_dir.to_path_buf();
+            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.clone());
+            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("whisper"));
+            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("resources"));
+            push_unique_existing_dir(
+                &mut seen_dirs,
+                &mut roots,
+                executable_dir.join("resources").join("whisper"),
+            );
+            push_unique_existing_dir(&mut seen_dirs, &mut roots, executable_dir.join("Resources"));
+            push_unique_existing_dir(
+                &mut seen_dirs,
+                &mut roots,
+                executable_dir.join("Resources").join("whisper"),
+            );
+            if let Some(parent_dir) = executable_dir.parent() {
+                push_unique_existing_dir(&mut seen_dirs, &mut roots, parent_dir.join("resources"));
+                push_unique_existing_dir(
+                    &mut seen_dirs,
+                    &mut roots,
+                    parent_dir.join("resources").join("whisper"),
+                );
+                push_unique_existing_dir(&mut seen_dirs, &mut roots, parent_dir.join("Resources"));
+                push_unique_existing_dir(
+                    &mut seen_dirs,
+                    &mut roots,
+                    parent_dir.join("Resources").join("whisper"),
+                );
+            }
+        }
+    }
+
+    if let Ok(current_dir) = std::env::current_dir() {
+        push_unique_existing_dir(
+            &mut seen_dirs,
+            &mut roots,
+            current_dir.join("src-tauri").join("resources"),
+        );
+        push_unique_existing_dir(
+            &mut seen_dirs,
+            &mut roots,
+            current_dir
+                .join("src-tauri")
+                .join("resources")
+                .join("whisper"),
+        );
+    }
+
+    roots
+}
+
+fn find_file_recursively(root: &Path, file_name: &str) -> Option<PathBuf> {
+    if !root.exists() {
+        return None;
+    }
+
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            let matches_name = path
+                .file_name()
+                .and_then(|candidate| candidate.to_str())
+                .map(|candidate| candidate.eq_ignore_ascii_case(file_name))
+                .unwrap_or(false);
+            if matches_name {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+// Lower is preferred; larger models rank first.
+fn whisper_model_priority(file_name: &str) -> usize {
+    match file_name {
+        "ggml-large-v3.bin" => 0,
+        "ggml-large-v3-turbo.bin" => 1,
+        "ggml-large-v2.bin" => 2,
+        "ggml-large-v1.bin" => 3,
+        "ggml-large.bin" => 4,
+        "ggml-medium.bin" => 5,
+        "ggml-medium.en.bin" => 6,
+        "ggml-small.bin" => 7,
+        "ggml-small.en.bin" => 8,
+        "ggml-base.bin" => 9,
+        "ggml-base.en.bin" => 10,
+        "ggml-tiny.bin" => 11,
+        "ggml-tiny.en.bin" => 12,
+        _ => 100,
+    }
+}
+
+fn find_whisper_model_recursively(root: &Path) -> Option<PathBuf> {
+    if !root.exists() {
+        return None;
+    }
+
+    let mut best_match: Option<(usize, u64, PathBuf)> = None;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            let is_ggml_model = path
+                .file_name()
+                .and_then(|candidate| candidate.to_str())
+                .map(|candidate| {
+                    let normalized = candidate.to_ascii_lowercase();
+                    normalized.starts_with("ggml-") && normalized.ends_with(".bin")
+                })
+                .unwrap_or(false);
+            if is_ggml_model {
+                let model_name = path
+                    .file_name()
+                    .and_then(|candidate| candidate.to_str())
+                    .map(|candidate| candidate.to_ascii_lowercase())
+                    .unwrap_or_default();
+                let priority = whisper_model_priority(&model_name);
+                let size = fs::metadata(&path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+                let should_replace = best_match
+                    .as_ref()
+                    .map(|(best_priority, best_size, _)| {
+                        priority < *best_priority
+                            || (priority == *best_priority && size > *best_size)
+                    })
+                    .unwrap_or(true);
+                if should_replace {
+                    best_match = Some((priority, size, path));
+                }
+            }
+        }
+    }
+
+    best_match.map(|(_, _, path)| path)
+}
+
+fn resolve_bundled_resource_candidates(
+    app: &tauri::AppHandle,
+    relative_paths: &[&str],
+    file_names: &[&str],
+) -> Option<String> {
+    for relative_path in relative_paths {
+        if let Some(path) = resolve_bundled_resource(app, relative_path) {
+            return Some(path);
+        }
+    }
+
+    for root in collect_resource_search_roots(app) {
+        for file_name in file_names {
+            if let Some(found_path) = find_file_recursively(&root, file_name) {
+                return Some(found_path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    None
+}
+
+fn format_exit_status(status: &std::process::ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        #[cfg(windows)]
+        {
+            let code_as_u32 = code as u32;
+            return format!("exit code {code} (0x{code_as_u32:08X})");
+        }
+        #[cfg(not(windows))]
+        {
+            return format!("exit code {code}");
+        }
+    }
+
+    "terminated by signal".to_string()
+}
+
+fn whisper_runtime_hint(status_code: Option<i32>) -> Option<&'static str> {
+    #[cfg(windows)]
+    if let Some(code) = status_code {
+        return match code as u32 {
+            0xC0000135 => Some(
+                "Windows reported STATUS_DLL_NOT_FOUND. Bundle whisper.cpp runtime DLLs (for example: whisper.dll, ggml.dll, ggml-base.dll, ggml-cpu.dll) beside whisper-cli.exe.",
+            ),
+            0xC000007B => Some(
+                "Windows reported STATUS_INVALID_IMAGE_FORMAT. Verify Whisper binary/DLL architecture matches the app (x64 vs x86).",
+            ),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+fn is_tiny_whisper_model_path(model_path: &str) -> bool {
+    Path::new(model_path)
+        .file_name()
+        .and_then(|candidate| candidate.to_str())
+        .map(|candidate| candidate.to_ascii_lowercase().contains("ggml-tiny"))
+        .unwrap_or(false)
+}
+
+// tinydiarize models are published as e.g. `ggml-small.en-tdrz.bin`.
+fn is_tinydiarize_model_path(model_path: &str) -> bool {
+    Path::new(model_path)
+        .file_name()
+        .and_then(|candidate| candidate.to_str())
+        .map(|candidate| candidate.to_ascii_lowercase().contains("tdrz"))
+        .unwrap_or(false)
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct WhisperOptions {
+    beam_size: Option<u32>,
+    best_of: Option<u32>,
+    initial_prompt: Option<String>,
+    offset_ms: Option<u64>,
+    duration_ms: Option<u64>,
+    // Extra output files to produce next to the plain-text transcript:
+    // "srt", "vtt", "json" or "csv".
+    output_formats: Option<Vec<String>>,
+    // Caps the characters per segment. Each segment becomes one srt/vtt cue,
+    // so this is what keeps subtitle lines short; txt output just gets more
+    // line breaks.
+    max_len: Option<u32>,
+    // Passes `--split-on-word` so `max_len` breaks segments between words
+    // instead of mid-word tokens. Only accepted with srt or vtt output.
+    split_on_word: Option<bool>,
+    // Number of previous-text tokens carried into each window. -1 keeps
+    // whisper's default, 0 disables carry-over, which also curbs repetition.
+    max_context: Option<i32>,
+    // Defaults to true. GPU use (CUDA, Metal, Vulkan, CoreML) depends on how
+    // the bundled binary was built; false passes `--no-gpu` to force the CPU.
+    use_gpu: Option<bool>,
+    // Passes `--suppress-nst` so non-speech tokens can't be sampled, which
+    // curbs hallucinated phrases over silence.
+    suppress_non_speech: Option<bool>,
+    // Token ids whisper must never emit, passed as `--suppress-tokens 1,2,3`.
+    // Only some builds accept the flag; the capability check reports it.
+    suppress_tokens: Option<Vec<i32>>,
+    // Adds token-level JSON output and merges the tokens into per-word
+    // timings (`words` in the detailed result).
+    word_timestamps: Option<bool>,
+    // Adds token-level JSON output and returns every segment with its
+    // tokens' probabilities (`segments` in the detailed result).
+    token_probabilities: Option<bool>,
+    // Emit `whisper:low_confidence` when the transcript's confidence (see
+    // `transcript_confidence`) is below this value in 0..=1. Setting it adds
+    // token-level JSON output so the confidence can be computed.
+    min_confidence: Option<f32>,
+    // Passes `--translate` so whisper outputs English whatever the spoken
+    // language. Needs a multilingual model.
+    translate: Option<bool>,
+    // Transcribe, then translate the same prepared input in a second pass;
+    // the translation comes back in `translated`.
+    both: Option<bool>,
+    // Set between the passes of `both`: the first keeps its working dir on
+    // success (a failure still cleans up), and the second reads the first's
+    // prepared WAV instead of audio bytes.
+    #[serde(skip)]
+    keep_input: bool,
+    #[serde(skip)]
+    prepared_input: Option<PathBuf>,
+    // Drop non-speech annotations like `[BLANK_AUDIO]` or `(wind blowing)`.
+    strip_annotations: Option<bool>,
+    // Heuristic sentence casing and end punctuation for models that don't
+    // punctuate; see `transcript::normalize_sentences`.
+    normalize_output: Option<bool>,
+    // Bring quiet recordings up before whisper sees them: peak normalization
+    // for 16-bit PCM WAV, ffmpeg's `loudnorm` for audio that gets converted.
+    normalize_audio: Option<bool>,
+    // Passed to whisper verbatim after every managed flag, for options this
+    // crate doesn't wrap. Flags that control input, model and output naming
+    // are rejected.
+    extra_args: Option<Vec<String>>,
+    // Label speaker turns. Uses `--tinydiarize` with a tdrz model, otherwise
+    // `--diarize`, which needs stereo audio with one speaker per channel.
+    diarize: Option<bool>,
+    // Expected number of speakers, passed as `--num-speakers` with `diarize`
+    // on builds that accept it and dropped with a warning elsewhere.
+    num_speakers: Option<u32>,
+    // Return the fully resolved command line as the transcript instead of
+    // running whisper. No temporary files are written.
+    dry_run: Option<bool>,
+    // Retry with the next smaller bundled model (up to twice) when whisper
+    // exits with an error, e.g. because the model doesn't fit in memory.
+    model_fallback: Option<bool>,
+    // Leave the working dir (input WAV and raw whisper output) on disk and
+    // report its path. Also enabled by GREEPY_WHISPER_KEEP=1.
+    keep_artifacts: Option<bool>,
+    // What the caller knows about the audio. Input that isn't already 16 kHz
+    // 16-bit PCM WAV is converted with ffmpeg first.
+    audio_format: Option<AudioFormatHint>,
+    // Which channel of a stereo recording to transcribe. Defaults to mixing
+    // them; pick one when the speech is only on one side, e.g. a phone call.
+    channel: Option<ChannelSelect>,
+    // Write the transcript here (and each extra output format next to it
+    // with that format's extension) and return the paths instead of the text.
+    output_path: Option<String>,
+    // Split the audio into windows of this length, transcribe them one by one
+    // and stitch the text back together. For recordings too long for one pass.
+    chunk_ms: Option<u64>,
+    // How much consecutive windows share, used to align the stitched text.
+    // Defaults to 2 seconds.
+    chunk_overlap_ms: Option<u64>,
+    // Registers the run under this id so `whisper_retry` can interrupt it
+    // and start over with different parameters.
+    job_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum ChannelSelect {
+    Left,
+    Right,
+    Mix,
+}
+
+impl ChannelSelect {
+    // Zero-based input channel, or `None` for a mixdown.
+    fn index(self) -> Option<u16> {
+        match self {
+            ChannelSelect::Left => Some(0),
+            ChannelSelect::Right => Some(1),
+            ChannelSelect::Mix => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AudioFormatHint {
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+    // "wav"/"pcm", or a container such as "mp3", "ogg", "webm" or "m4a".
+    codec: Option<String>,
+}
+
+impl AudioFormatHint {
+    fn is_wav(&self) -> bool {
+        self.codec
+            .as_deref()
+            .map(|codec| matches!(codec.trim().to_ascii_lowercase().as_str(), "wav" | "pcm"))
+            .unwrap_or(true)
+    }
+}
+
+// Decides whether the input has to go through ffmpeg. The WAV header wins
+// when there is one, and a hint that contradicts it is rejected. Input that
+// is neither a WAV nor described by a hint is handed to whisper unchanged.
+fn audio_needs_conversion(
+    wav_info: Option<&audio::WavInfo>,
+    container: Option<&str>,
+    hint: Option<&AudioFormatHint>,
+    target_channels: u16,
+) -> CommandResult<bool> {
+    if container == Some("wav") && wav_info.is_none() {
+        return Err(CommandError::InvalidArgument(
+            "The audio has a RIFF/WAVE header but no readable fmt or data chunk; the WAV file is damaged."
+                .to_string(),
+        ));
+    }
+    if let (Some(info), Some(hint)) = (wav_info, hint) {
+        let mismatch = if !hint.is_wav() {
+            Some(format!(
+                "codec '{}' but the audio is a WAV file",
+                hint.codec.as_deref().unwrap_or_default()
+            ))
+        } else if hint
+            .sample_rate
+            .is_some_and(|rate| rate != info.sample_rate)
+        {
+            Some(format!(
+                "{} Hz but the WAV header says {} Hz",
+                hint.sample_rate.unwrap_or_default(),
+                info.sample_rate
+            ))
+        } else if hint
+            .channels
+            .is_some_and(|channels| channels != info.channels)
+        {
+            Some(format!(
+                "{} channel(s) but the WAV header says {}",
+                hint.channels.unwrap_or_default(),
+                info.channels
+            ))
+        } else {
+            None
+        };
+        if let Some(mismatch) = mismatch {
+            return Err(CommandError::InvalidArgument(format!(
+                "audioFormat says {mismatch}."
+            )));
+        }
+    }
+    Ok(match (wav_info, hint) {
+        (Some(info), _) => !info.is_whisper_ready(target_channels == 2),
+        (None, Some(hint)) => {
+            !hint.is_wav()
+                || hint
+                    .sample_rate
+                    .is_some_and(|rate| rate != audio::WHISPER_SAMPLE_RATE)
+                || hint
+                    .channels
+                    .is_some_and(|channels| channels > 2 || (target_channels == 2 && channels != 2))
+        }
+        // Anything ffmpeg recognizes by its magic bytes gets converted.
+        (None, None) if container.is_some() => true,
+        (None, None) => {
+            return Err(CommandError::InvalidArgument(
+                "Unrecognized audio format: not a WAV file and not a known mp3, flac, ogg, webm, m4a or aac stream. Pass audioFormat.codec to convert it with ffmpeg anyway.".to_string(),
+            ))
+        }
+    })
+}
+
+const WHISPER_OUTPUT_FORMATS: &[(&str, &str)] = &[
+    ("txt", "-otxt"),
+    ("srt", "-osrt"),
+    ("vtt", "-ovtt"),
+    ("json", "-oj"),
+    // One row per segment: start and end in milliseconds, then the text.
+    ("csv", "-ocsv"),
+];
+
+struct WhisperState {
+    capabilities: Mutex<HashMap<String, WhisperCapabilities>>,
+    queue: TranscriptionQueue,
+    // In-progress `whisper_transcribe_chunks` uploads, keyed by job id.
+    uploads: Mutex<HashMap<String, ChunkUpload>>,
+    // Running transcriptions that were given a `jobId`.
+    jobs: Mutex<HashMap<String, WhisperJob>>,
+    // The last WHISPER_FAILURE_HISTORY failed runs, oldest first.
+    failures: Mutex<VecDeque<WhisperFailure>>,
+}
+
+const WHISPER_FAILURE_HISTORY: usize = 10;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperFailure {
+    // Milliseconds since the Unix epoch.
+    failed_at: u64,
+    command_line: String,
+    // `format_exit_status`, or why the process couldn't be launched.
+    status: String,
+    stdout: String,
+    stderr: String,
+}
+
+#[derive(Default)]
+struct WhisperJob {
+    // The whisper process currently working on the job, if any.
+    pid: Option<u32>,
+    // Set by `whisper_retry`; picked up once the killed run returns.
+    retry: Option<WhisperRetry>,
+}
+
+struct WhisperRetry {
+    language: Option<String>,
+    options: WhisperOptions,
+}
+
+struct ChunkUpload {
+    dir: PathBuf,
+    file: fs::File,
+    len: u64,
+}
+
+// Caps how many whisper processes run at once; every transcription path
+// takes a permit before launching the binary.
+struct TranscriptionQueue {
+    active: Mutex<usize>,
+    available: Condvar,
+    limit: usize,
+}
+
+struct QueuePermit<'a> {
+    queue: &'a TranscriptionQueue,
+}
+
+impl TranscriptionQueue {
+    fn new(limit: usize) -> Self {
+        TranscriptionQueue {
+            active: Mutex::new(0),
+            available: Condvar::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    fn from_env() -> Self {
+        let limit = std::env::var("GREEPY_WHISPER_CONCURRENCY")
+            .ok()
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .unwrap_or(1);
+        TranscriptionQueue::new(limit)
+    }
+
+    fn acquire(&self) -> QueuePermit<'_> {
+        let mut active = lock_recovering(&self.active, "transcription queue");
+        while *active >= self.limit {
+            active = self
+                .available
+                .wait(active)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *active += 1;
+        QueuePermit { queue: self }
+    }
+}
+
+impl Drop for QueuePermit<'_> {
+    fn drop(&mut self) {
+        *lock_recovering(&self.queue.active, "transcription queue") -= 1;
+        self.queue.available.notify_one();
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperCapabilities {
+    version: Option<String>,
+    supports_translate: bool,
+    supports_json: bool,
+    supports_vad: bool,
+    supports_diarize: bool,
+    supports_tinydiarize: bool,
+    supports_detect_language: bool,
+    supports_gpu_toggle: bool,
+    // GPU backend libraries found beside a dynamically linked binary, e.g.
+    // "cuda" for ggml-cuda.dll. Static builds report an empty list.
+    gpu_backends: Vec<String>,
+    flags: Vec<String>,
+}
+
+impl WhisperCapabilities {
+    fn from_help(help: &str) -> Self {
+        let mut flags: Vec<String> = help
+            .split_whitespace()
+            .map(|token| token.trim_end_matches(','))
+            .filter(|token| {
+                token.len() > 1
+                    && token.starts_with('-')
+                    && token
+                        .trim_start_matches('-')
+                        .chars()
+                        .next()
+                        .map(|first| first.is_ascii_alphabetic())
+                        .unwrap_or(false)
+            })
+            .map(str::to_string)
+            .collect();
+        flags.sort();
+        flags.dedup();
+        let has = |flag: &str| flags.iter().any(|candidate| candidate == flag);
+        let version = help
+            .lines()
+            .find(|line| line.to_ascii_lowercase().contains("version"))
+            .map(|line| line.trim().to_string());
+        WhisperCapabilities {
+            version,
+            supports_translate: has("--translate"),
+            supports_json: has("--output-json"),
+            supports_vad: has("--vad"),
+            supports_diarize: has("--diarize"),
+            supports_tinydiarize: has("--tinydiarize"),
+            supports_detect_language: has("--detect-language"),
+            supports_gpu_toggle: has("--no-gpu"),
+            gpu_backends: Vec::new(),
+            flags,
+        }
+    }
+
+    fn require(&self, flag: &str) -> CommandResult<()> {
+        if self.flags.iter().any(|candidate| candidate == flag) {
+            return Ok(());
+        }
+        Err(CommandError::Whisper(format!(
+            "The whisper binary does not support {flag}. Check whisper_capabilities for the options this build accepts."
+        )))
+    }
+}
+
+fn probe_whisper_capabilities(binary: &str) -> CommandResult<WhisperCapabilities> {
+    let mut help_command = Command::new(binary);
+    help_command.arg("--help");
+    hide_console_window(&mut help_command);
+    let output = help_command.output().map_err(|error| {
+        CommandError::SpawnFailed(format!(
+            "Failed to launch whisper binary '{binary}': {error}"
+        ))
+    })?;
+    // whisper-cli prints usage to stderr and may exit non-zero for --help.
+    let help = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut capabilities = WhisperCapabilities::from_help(&help);
+    capabilities.gpu_backends = detect_gpu_backends(binary);
+    Ok(capabilities)
+}
+
+fn detect_gpu_backends(binary: &str) -> Vec<String> {
+    const BACKENDS: &[&str] = &["cuda", "metal", "vulkan", "sycl", "opencl", "coreml"];
+    let Some(binary_dir) = Path::new(binary).parent().filter(|dir| dir.is_dir()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(binary_dir) else {
+        return Vec::new();
+    };
+    let file_names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .map(|name| name.to_ascii_lowercase())
+        })
+        .collect();
+    BACKENDS
+        .iter()
+        .filter(|backend| {
+            file_names.iter().any(|name| {
+                name.contains(&format!("ggml-{backend}"))
+                    || name.contains(&format!("whisper-encoder-{backend}"))
+            })
+        })
+        .map(|backend| backend.to_string())
+        .collect()
+}
+
+fn cached_whisper_capabilities(
+    app: &tauri::AppHandle,
+    binary: &str,
+) -> CommandResult<WhisperCapabilities> {
+    let state = app.state::<WhisperState>();
+    if let Some(capabilities) =
+        lock_recovering(&state.capabilities, "whisper capabilities").get(binary)
+    {
+        return Ok(capabilities.clone());
+    }
+    let capabilities = probe_whisper_capabilities(binary)?;
+    lock_recovering(&state.capabilities, "whisper capabilities")
+        .insert(binary.to_string(), capabilities.clone());
+    Ok(capabilities)
+}
+
+// Long-form flags implied by the requested options, checked against the
+// binary's `--help` before any temp files are written.
+fn optional_whisper_flags(options: &WhisperOptions, output_formats: &[&str]) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if options.beam_size.is_some() {
+        flags.push("--beam-size");
+    }
+    if options.best_of.is_some() {
+        flags.push("--best-of");
+    }
+    if resolve_non_empty(options.initial_prompt.clone()).is_some() {
+        flags.push("--prompt");
+    }
+    if options.offset_ms.is_some() {
+        flags.push("--offset-t");
+    }
+    if options.duration_ms.is_some() {
+        flags.push("--duration");
+    }
+    if options.max_len.is_some() {
+        flags.push("--max-len");
+    }
+    if options.split_on_word == Some(true) {
+        flags.push("--split-on-word");
+    }
+    if options.translate == Some(true) {
+        flags.push("--translate");
+    }
+    if options.max_context.is_some() {
+        flags.push("--max-context");
+    }
+    if options.use_gpu == Some(false) {
+        flags.push("--no-gpu");
+    }
+    if options.suppress_non_speech == Some(true) {
+        flags.push("--suppress-nst");
+    }
+    if options
+        .suppress_tokens
+        .as_ref()
+        .is_some_and(|tokens| !tokens.is_empty())
+    {
+        flags.push("--suppress-tokens");
+    }
+    if options.word_timestamps == Some(true)
+        || options.token_probabilities == Some(true)
+        || options.min_confidence.is_some()
+    {
+        flags.push("--output-json-full");
+    }
+    for format in output_formats {
+        match *format {
+            "srt" => flags.push("--output-srt"),
+            "vtt" => flags.push("--output-vtt"),
+            "json" => flags.push("--output-json"),
+            "csv" => flags.push("--output-csv"),
+            _ => {}
+        }
+    }
+    flags
+}
+// Flags whose values the transcription pipeline sets itself; overriding them
+// would point whisper at a different input, model or output file.
+const MANAGED_WHISPER_FLAGS: &[&str] = &[
+    "-m",
+    "--model",
+    "-f",
+    "--file",
+    "-of",
+    "--output-file",
+    "-l",
+    "--language",
+];
+
+fn validate_extra_args(extra_args: &[String]) -> CommandResult<()> {
+    for arg in extra_args {
+        let flag = arg.split('=').next().unwrap_or_default();
+        if MANAGED_WHISPER_FLAGS.contains(&flag) {
+            return Err(CommandError::InvalidArgument(format!(
+                "extraArgs cannot override the managed whisper flag '{flag}'."
+            )));
+        }
+    }
+    Ok(())
+}
+
+const MAX_WHISPER_SEGMENT_LEN: u32 = 1000;
+const MAX_WHISPER_CONTEXT_TOKENS: i32 = 224;
+// Only a hint; more than this is allowed but probably a mistake.
+const MAX_EXPECTED_SPEAKERS: u32 = 10;
+
+fn resolve_output_formats(requested: Option<&Vec<String>>) -> CommandResult<Vec<&'static str>> {
+    let mut formats = vec!["txt"];
+    for name in requested.into_iter().flatten() {
+        let normalized = name.trim().to_ascii_lowercase();
+        let Some((format, _)) = WHISPER_OUTPUT_FORMATS
+            .iter()
+            .find(|(format, _)| *format == normalized)
+        else {
+            let supported: Vec<&str> = WHISPER_OUTPUT_FORMATS
+                .iter()
+                .map(|(format, _)| *format)
+                .collect();
+            return Err(CommandError::InvalidArgument(format!(
+                "Unsupported output format '{name}'. Supported formats: {}.",
+                supported.join(", ")
+            )));
+        };
+        if !formats.contains(format) {
+            formats.push(*format);
+        }
+    }
+    Ok(formats)
+}
+
+fn output_format_flag(format: &str) -> &'static str {
+    WHISPER_OUTPUT_FORMATS
+        .iter()
+        .find(|(candidate, _)| *candidate == format)
+        .map(|(_, flag)| *flag)
+        .unwrap_or("-otxt")
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TranscriptMetrics {
+    audio_ms: Option<u64>,
+    processing_ms: u64,
+    rtf: Option<f64>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TranscriptResult {
+    transcript: String,
+    metrics: TranscriptMetrics,
+    // Contents of the additional `outputFormats`, keyed by format name.
+    outputs: BTreeMap<String, String>,
+    // The transcript as whisper wrote it, when `stripAnnotations` changed it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_transcript: Option<String>,
+    // Only filled when `wordTimestamps` is enabled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    words: Vec<WordTiming>,
+    // Only filled when `diarize` or `tokenProbabilities` is enabled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<TranscriptSegment>,
+    // Working directory left on disk by `keepArtifacts`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifacts_dir: Option<String>,
+    // The file whisper read inside `artifacts_dir`; also only set then.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_path: Option<String>,
+    // Only filled when token-level JSON was produced (`wordTimestamps` or
+    // `minConfidence`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<f32>,
+    // The `--translate` pass of a `both` transcription.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translated: Option<Box<TranscriptResult>>,
+}
+
+#[derive(Serialize, Clone)]
+struct LowConfidencePayload {
+    confidence: f32,
+    threshold: f32,
+}
+
+// Geometric mean of the text tokens' probabilities in whisper.cpp's full JSON
+// output, i.e. exp of the average token log-probability. whisper.cpp doesn't
+// report per-segment avg_logprob itself, so this is derived from the tokens.
+fn transcript_confidence(json: &str) -> Option<f32> {
+    let root = serde_json::from_str::<serde_json::Value>(json).ok()?;
+    let mut log_sum = 0.0f64;
+    let mut count = 0u32;
+    let tokens = root["transcription"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|segment| segment["tokens"].as_array())
+        .flatten();
+    for token in tokens {
+        let text = token["text"].as_str().unwrap_or_default();
+        if text.trim().is_empty() || text.starts_with("[_") {
+            continue;
+        }
+        let Some(probability) = token["p"].as_f64() else {
+            continue;
+        };
+        log_sum += probability.max(1e-6).ln();
+        count += 1;
+    }
+    (count > 0).then(|| (log_sum / f64::from(count)).exp() as f32)
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TranscriptSegment {
+    start_ms: u64,
+    end_ms: u64,
+    text: String,
+    speaker: Option<u32>,
+    // Only filled when `tokenProbabilities` is enabled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<TranscriptToken>,
+}
+
+#[derive(Serialize, Clone)]
+struct TranscriptToken {
+    // As whisper produced it, leading space included, so concatenating the
+    // tokens reproduces the segment text.
+    text: String,
+    p: f32,
+}
+
+// `--diarize` tags each segment with a `speaker` string ("0", "1" or "?").
+// tinydiarize only flags `speaker_turn_next`, so turns alternate between
+// speakers 0 and 1. `speakers` is `Some(tinydiarize)` when diarizing.
+fn parse_segments(json: &str, speakers: Option<bool>, with_tokens: bool) -> Vec<TranscriptSegment> {
+    let Ok(root) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    let mut turn_speaker = 0;
+    let mut segments = Vec::new();
+    for segment in root["transcription"].as_array().into_iter().flatten() {
+        let speaker = match speakers {
+            Some(true) => Some(turn_speaker),
+            Some(false) => segment["speaker"]
+                .as_str()
+                .and_then(|speaker| speaker.trim().parse().ok()),
+            None => None,
+        };
+        if segment["speaker_turn_next"].as_bool() == Some(true) {
+            turn_speaker = 1 - turn_speaker;
+        }
+        let text = segment["text"].as_str().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        // Special tokens such as `[_BEG_]` carry no text of their own.
+        let tokens = segment["tokens"]
+            .as_array()
+            .filter(|_| with_tokens)
+            .into_iter()
+            .flatten()
+            .filter_map(|token| {
+                let text = token["text"].as_str()?;
+                (!text.is_empty() && !text.starts_with("[_")).then(|| TranscriptToken {
+                    text: text.to_string(),
+                    p: token["p"].as_f64().unwrap_or_default() as f32,
+                })
+            })
+            .collect();
+        segments.push(TranscriptSegment {
+            start_ms: segment["offsets"]["from"].as_u64().unwrap_or_default(),
+            end_ms: segment["offsets"]["to"].as_u64().unwrap_or_default(),
+            text: text.to_string(),
+            speaker,
+            tokens,
+        });
+    }
+    segments
+}
+
+// One "Speaker N: ..." line per run of segments from the same speaker.
+fn label_speakers(segments: &[TranscriptSegment]) -> String {
+    let mut lines: Vec<(Option<u32>, String)> = Vec::new();
+    for segment in segments {
+        match lines.last_mut() {
+            Some((speaker, text)) if *speaker == segment.speaker => {
+                text.push(' ');
+                text.push_str(&segment.text);
+            }
+            _ => lines.push((segment.speaker, segment.text.clone())),
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(speaker, text)| match speaker {
+            Some(speaker) => format!("Speaker {}: {text}", speaker + 1),
+            None => format!("Speaker ?: {text}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WordTiming {
+    word: String,
+    start_ms: u64,
+    end_ms: u64,
+    // Mean probability of the tokens that make up the word.
+    probability: f32,
+}
+
+// Rebuilds words from the `tokens` arrays of whisper.cpp's full JSON output.
+// A token starting with a space opens a new word; special tokens such as
+// `[_BEG_]` or `[_TT_150]` carry no text and are skipped.
+fn parse_word_timings(json: &str) -> Vec<WordTiming> {
+    let Ok(root) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    let tokens = root["transcription"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|segment| segment["tokens"].as_array())
+        .flatten();
+
+    let mut words: Vec<WordTiming> = Vec::new();
+    let mut token_count = 0u32;
+    for token in tokens {
+        let text = token["text"].as_str().unwrap_or_default();
+        if text.is_empty() || text.starts_with("[_") {
+            continue;
+        }
+        let start_ms = token["offsets"]["from"].as_u64().unwrap_or_default();
+        let end_ms = token["offsets"]["to"].as_u64().unwrap_or(start_ms);
+        let probability = token["p"].as_f64().unwrap_or_default() as f32;
+        match words.last_mut() {
+            Some(word) if !text.starts_with(' ') => {
+                word.word.push_str(text);
+                word.end_ms = end_ms;
+                word.probability += probability;
+                token_count += 1;
+            }
+            _ => {
+                if let Some(word) = words.last_mut() {
+                    word.probability /= token_count as f32;
+                }
+                words.push(WordTiming {
+                    word: text.trim_start().to_string(),
+                    start_ms,
+                    end_ms,
+                    probability,
+                });
+                token_count = 1;
+            }
+        }
+    }
+    if let Some(word) = words.last_mut() {
+        word.probability /= token_count as f32;
+    }
+    words.retain(|word| !word.word.trim().is_empty());
+    words
+}
+
+#[derive(Serialize, Clone)]
+struct WhisperWarningPayload {
+    message: String,
+}
+
+const MAX_WHISPER_BEAM_SIZE: u32 = 16;
+const MAX_WHISPER_BEST_OF: u32 = 16;
+// whisper.cpp only keeps the last ~224 prompt tokens, so anything longer is
+// dropped from the front anyway.
+const MAX_WHISPER_PROMPT_CHARS: usize = 896;
+
+fn truncate_prompt_tail(prompt: &str, max_chars: usize) -> &str {
+    let char_count = prompt.chars().count();
+    if char_count <= max_chars {
+        return prompt;
+    }
+    let start = prompt
+        .char_indices()
+        .nth(char_count - max_chars)
+        .map(|(index, _)| index)
+        .unwrap_or(0);
+    &prompt[start..]
+}
+
+fn validate_bounded_option(name: &str, value: Option<u32>, max: u32) -> CommandResult<()> {
+    match value {
+        Some(0) => Err(CommandError::InvalidArgument(format!(
+            "{name} must be at least 1."
+        ))),
+        Some(value) if value > max => Err(CommandError::InvalidArgument(format!(
+            "{name} must be at most {max} (got {value})."
+        ))),
+        _ => Ok(()),
+    }
+}
+
+const WHISPER_TEMP_DIR_PREFIX: &str = "greepy-whisper-";
+const STALE_WHISPER_TEMP_DIR_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn whisper_temp_base() -> PathBuf {
+    resolve_non_empty(std::env::var("GREEPY_WHISPER_TMP").ok())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+fn sweep_stale_whisper_dirs(base: &Path, max_age: Duration) -> std::io::Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in fs::read_dir(base)?.flatten() {
+        let path = entry.path();
+        let is_whisper_dir = path.is_dir()
+            && entry
+                .file_name()
+                .to_str()
+                .map(|name| name.starts_with(WHISPER_TEMP_DIR_PREFIX))
+                .unwrap_or(false);
+        if !is_whisper_dir {
+            continue;
+        }
+
+        let is_stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map(|age| age >= max_age)
+            .unwrap_or(false);
+        if is_stale && fs::remove_dir_all(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn resolve_whisper_binary(app: &tauri::AppHandle, whisper_binary: Option<String>) -> String {
+    resolve_user_path(whisper_binary)
+        .or_else(|| resolve_user_path(app_config(app).whisper_binary))
+        .or_else(|| resolve_user_path(std::env::var("GREEPY_WHISPER_BIN").ok()))
+        .or_else(|| {
+            resolve_bundled_resource_candidates(
+                app,
+                &[
+                    "whisper-cli.exe",
+                    "whisper-cli",
+                    "whisper/whisper-cli.exe",
+                    "resources/whisper/whisper-cli.exe",
+                    "whisper/whisper-cli",
+                    "resources/whisper/whisper-cli",
+                ],
+                &["whisper-cli.exe", "whisper-cli"],
+            )
+        })
+        .unwrap_or_else(|| "whisper-cli".to_string())
+}
+
+// Lets users default to e.g. a faster medium model while a large one is also
+// installed. Falls through to the priority ranking when the file isn't found.
+fn find_preferred_whisper_model(app: &tauri::AppHandle) -> Option<String> {
+    let preferred = resolve_non_empty(app_config(app).whisper_prefer_model)
+        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_PREFER_MODEL").ok()))?;
+    collect_resource_search_roots(app)
+        .iter()
+        .find_map(|root| find_file_recursively(root, &preferred))
+        .map(|found_model| found_model.to_string_lossy().to_string())
+}
+
+fn resolve_whisper_model_path(
+    app: &tauri::AppHandle,
+    model_path: Option<String>,
+) -> CommandResult<String> {
+    let resolved_model_path = resolve_user_path(model_path)
+        .or_else(|| resolve_user_path(app_config(app).whisper_model_path))
+        .or_else(|| resolve_user_path(std::env::var("GREEPY_WHISPER_MODEL_PATH").ok()))
+        .or_else(|| find_preferred_whisper_model(app))
+        .or_else(|| {
+            resolve_bundled_resource_candidates(
+                app,
+                &[
+                    "ggml-large-v3.bin",
+                    "ggml-large-v3-turbo.bin",
+                    "ggml-medium.bin",
+                    "ggml-medium.en.bin",
+                    "ggml-base.bin",
+                    "ggml-base.en.bin",
+                    "ggml-tiny.en.bin",
+                    "ggml-tiny.bin",
+                    "whisper/ggml-large-v3.bin",
+                    "resources/whisper/ggml-large-v3.bin",
+                    "whisper/ggml-large-v3-turbo.bin",
+                    "resources/whisper/ggml-large-v3-turbo.bin",
+                    "whisper/ggml-medium.bin",
+                    "resources/whisper/ggml-medium.bin",
+                    "whisper/ggml-medium.en.bin",
+                    "resources/whisper/ggml-medium.en.bin",
+                    "whisper/ggml-base.bin",
+                    "resources/whisper/ggml-base.bin",
+                    "whisper/ggml-base.en.bin",
+                    "resources/whisper/ggml-base.en.bin",
+                    "whisper/ggml-tiny.en.bin",
+                    "resources/whisper/ggml-tiny.en.bin",
+                    "whisper/ggml-tiny.bin",
+                    "resources/whisper/ggml-tiny.bin",
+                ],
+                &[
+                    "ggml-large-v3.bin",
+                    "ggml-large-v3-turbo.bin",
+                    "ggml-large-v2.bin",
+                    "ggml-large-v1.bin",
+                    "ggml-large.bin",
+                    "ggml-medium.bin",
+                    "ggml-medium.en.bin",
+                    "ggml-small.bin",
+                    "ggml-small.en.bin",
+                    "ggml-base.bin",
+                    "ggml-base.en.bin",
+                    "ggml-tiny.en.bin",
+                    "ggml-tiny.bin",
+                ],
+            )
+        })
+        .or_else(|| {
+            for root in collect_resource_search_roots(app) {
+                if let Some(found_model) = find_whisper_model_recursively(&root) {
+                    return Some(found_model.to_string_lossy().to_string());
+                }
+            }
+            None
+        })
+        .ok_or_else(|| {
+            CommandError::Whisper("Whisper model path is missing. Set GREEPY_WHISPER_MODEL_PATH, pass modelPath, or select a larger local model file such as ggml-large-v3.bin.".to_string())
+        })?;
+    if is_tiny_whisper_model_path(&resolved_model_path) {
+        return Err(CommandError::Whisper(
+            "Tiny Whisper models are disabled. Select a larger model such as ggml-large-v3.bin."
+                .to_string(),
+        ));
+    }
+    Ok(resolved_model_path)
+}
+
+// Every `ggml-*.bin` file under the resource search roots.
+fn collect_whisper_models(app: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut models = Vec::new();
+    let mut pending = collect_resource_search_roots(app);
+    let mut seen = HashSet::new();
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let is_ggml_model = path
+                .file_name()
+                .and_then(|candidate| candidate.to_str())
+                .map(|candidate| {
+                    let normalized = candidate.to_ascii_lowercase();
+                    normalized.starts_with("ggml-") && normalized.ends_with(".bin")
+                })
+                .unwrap_or(false);
+            // Search roots nest (`resources` and `resources/whisper`), so the
+            // same file can be reached twice.
+            if is_ggml_model && seen.insert(path.clone()) {
+                models.push(path);
+            }
+        }
+    }
+    models
+}
+
+fn model_file_name(model_path: &Path) -> String {
+    model_path
+        .file_name()
+        .and_then(|candidate| candidate.to_str())
+        .map(|candidate| candidate.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+// The next model down the size ranking from `current_model`, skipping tiny
+// models since those are never used.
+fn find_smaller_whisper_model(app: &tauri::AppHandle, current_model: &str) -> Option<String> {
+    let current_priority = whisper_model_priority(&model_file_name(Path::new(current_model)));
+    collect_whisper_models(app)
+        .into_iter()
+        .filter(|path| !is_tiny_whisper_model_path(&path.to_string_lossy()))
+        .map(|path| (whisper_model_priority(&model_file_name(&path)), path))
+        .filter(|(priority, _)| *priority > current_priority && *priority < 100)
+        .min_by_key(|(priority, _)| *priority)
+        .map(|(_, path)| path.to_string_lossy().to_string())
+}
+
+// Copies a whisper command with its `-m` value swapped for another model.
+fn with_whisper_model(command: &Command, model_path: &str) -> Command {
+    let mut replaced = Command::new(command.get_program());
+    let mut args = command.get_args();
+    while let Some(arg) = args.next() {
+        replaced.arg(arg);
+        if arg == "-m" {
+            args.next();
+            replaced.arg(model_path);
+        }
+    }
+    hide_console_window(&mut replaced);
+    replaced
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperModelInfo {
+    path: String,
+    file_name: String,
+    size_bytes: u64,
+    // tiny / base / small / medium / large, when the name says.
+    size_tier: Option<String>,
+    is_english_only: bool,
+    is_multilingual: bool,
+}
+
+const WHISPER_SIZE_TIERS: &[&str] = &["tiny", "base", "small", "medium", "large"];
+
+// Derived from the conventional file names, e.g. `ggml-medium.en.bin`,
+// `ggml-base.en-q5_1.bin` or `ggml-large-v3-turbo.bin`.
+fn whisper_model_info(model_path: &Path) -> WhisperModelInfo {
+    let file_name = model_file_name(model_path);
+    let stem = file_name
+        .trim_start_matches("ggml-")
+        .trim_end_matches(".bin");
+    let is_english_only = stem
+        .match_indices(".en")
+        .any(|(index, _)| matches!(stem[index + 3..].chars().next(), None | Some('-')));
+    let size_tier = WHISPER_SIZE_TIERS
+        .iter()
+        .find(|tier| stem.starts_with(*tier))
+        .map(|tier| tier.to_string());
+    WhisperModelInfo {
+        path: model_path.to_string_lossy().to_string(),
+        size_bytes: fs::metadata(model_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0),
+        file_name,
+        size_tier,
+        is_english_only,
+        is_multilingual: !is_english_only,
+    }
+}
+
+const MAX_MODEL_FALLBACKS: usize = 2;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperFallbackPayload {
+    from_model: String,
+    to_model: String,
+    reason: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperRestartedPayload {
+    job_id: String,
+    language: Option<String>,
+}
+
+#[cfg_attr(not(windows), allow(unused_variables))]
+fn hide_console_window(command: &mut Command) {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+}
+
+// Batch workers can start in the same millisecond, and another app instance
+// may share the temp base, so the pid and a counter keep names unique.
+fn whisper_working_dir_path() -> PathBuf {
+    static NEXT_WORKING_DIR: AtomicU64 = AtomicU64::new(0);
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    whisper_temp_base().join(format!(
+        "{WHISPER_TEMP_DIR_PREFIX}{stamp}-{}-{}",
+        std::process::id(),
+        NEXT_WORKING_DIR.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// Fails if the directory already exists rather than sharing it with another
+// run that would overwrite its files and delete it when done.
+fn create_fresh_dir(dir: &Path) -> CommandResult<()> {
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::create_dir(dir).map_err(|error| {
+        CommandError::Io(format!(
+            "Failed to create working directory '{}': {error}",
+            dir.display()
+        ))
+    })
+}
+
+fn create_whisper_working_dir() -> CommandResult<PathBuf> {
+    let working_dir = whisper_working_dir_path();
+    create_fresh_dir(&working_dir)?;
+    Ok(working_dir)
+}
+
+// Renders a command the way it could be pasted into a shell, quoting any
+// argument with whitespace or quotes in it.
+fn format_command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            {
+                format!("\"{}\"", arg.replace('"', "\\\""))
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Writes the audio into the working dir and checks that all of it landed on
+// disk; a truncated input would otherwise produce a plausible but wrong
+// transcript. The working dir is removed on failure.
+fn write_whisper_input(
+    working_dir: &Path,
+    file_name: &str,
+    audio_bytes: &[u8],
+) -> CommandResult<PathBuf> {
+    let input_audio_path = working_dir.join(file_name);
+    let written = fs::write(&input_audio_path, audio_bytes)
+        .and_then(|_| fs::metadata(&input_audio_path))
+        .map(|metadata| metadata.len());
+    let error = match written {
+        Ok(len) if len == audio_bytes.len() as u64 => return Ok(input_audio_path),
+        Ok(len) => CommandError::Io(format!(
+            "Temporary audio file is incomplete ({len} of {} bytes written) in {}.",
+            audio_bytes.len(),
+            working_dir.display()
+        )),
+        Err(error) if error.kind() == std::io::ErrorKind::StorageFull => {
+            CommandError::Io(format!(
+                "Not enough disk space to write the temporary audio file ({} bytes) in {}. Free up space or point GREEPY_WHISPER_TMP at another drive.",
+                audio_bytes.len(),
+                working_dir.display()
+            ))
+        }
+        Err(error) => CommandError::Io(format!("Failed to write temporary audio file: {error}")),
+    };
+    let _ = fs::remove_dir_all(working_dir);
+    Err(error)
+}
+
+const DEFAULT_MAX_AUDIO_BYTES: u64 = 500 * 1024 * 1024;
+
+fn max_audio_bytes(app: &tauri::AppHandle) -> u64 {
+    app_config(app)
+        .whisper_max_audio_bytes
+        .or_else(|| {
+            std::env::var("GREEPY_WHISPER_MAX_AUDIO_BYTES")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+        })
+        .filter(|limit| *limit > 0)
+        .unwrap_or(DEFAULT_MAX_AUDIO_BYTES)
+}
+
+fn check_audio_size(len: u64, limit: u64) -> CommandResult<()> {
+    if len <= limit {
+        return Ok(());
+    }
+    Err(CommandError::InvalidArgument(format!(
+        "Audio is {} MB, above the {} MB limit. Split it into smaller pieces with the offsetMs/durationMs options, or raise GREEPY_WHISPER_MAX_AUDIO_BYTES.",
+        len / (1024 * 1024),
+        limit / (1024 * 1024)
+    )))
+}
+
+// Probes the destination directory up front so a long transcription doesn't
+// finish only to fail on a read-only or missing folder.
+fn check_output_writable(output_path: &Path) -> CommandResult<()> {
+    let dir = output_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if !dir.is_dir() {
+        return Err(CommandError::InvalidArgument(format!(
+            "Output directory '{}' does not exist.",
+            dir.display()
+        )));
+    }
+    let probe = dir.join(format!(".greepy-write-test-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|error| {
+        CommandError::InvalidArgument(format!(
+            "Output directory '{}' is not writable: {error}",
+            dir.display()
+        ))
+    })?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+const NO_SPEECH_DETECTED: &str = "Transcription completed but no speech was detected.";
+const MIN_CHUNK_MS: u64 = 10_000;
+const DEFAULT_CHUNK_OVERLAP_MS: u64 = 2_000;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ChunkProgressPayload {
+    completed: usize,
+    total: usize,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+// Decodes the input to 16 kHz mono samples, converting it with ffmpeg first
+// unless it already is that format.
+fn decode_mono_samples(audio_bytes: &[u8], options: &WhisperOptions) -> CommandResult<Vec<i16>> {
+    if let Some(samples) = audio::mono_pcm16_samples(audio_bytes) {
+        return Ok(samples);
+    }
+    let working_dir = create_whisper_working_dir()?;
+    let extension = options
+        .audio_format
+        .as_ref()
+        .and_then(|format| resolve_non_empty(format.codec.clone()))
+        .unwrap_or_else(|| "audio".to_string());
+    let converted = write_whisper_input(&working_dir, &format!("source.{extension}"), audio_bytes)
+        .and_then(|source| {
+            let output = working_dir.join("mono.wav");
+            audio::convert_with_ffmpeg(
+                &source,
+                &output,
+                1,
+                options.channel.and_then(ChannelSelect::index),
+                false,
+            )?;
+            Ok(fs::read(output)?)
+        });
+    let _ = fs::remove_dir_all(&working_dir);
+    audio::mono_pcm16_samples(&converted?).ok_or_else(|| {
+        CommandError::Io("ffmpeg did not produce 16 kHz mono PCM audio.".to_string())
+    })
+}
+
+// Transcribes overlapping windows of the audio through the regular pipeline
+// (and so through the queue) and stitches the text on the overlap.
+fn whisper_transcribe_chunked(
+    app: &tauri::AppHandle,
+    audio_bytes: &[u8],
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+    chunk_ms: u64,
+) -> CommandResult<TranscriptResult> {
+    let overlap_ms = options.chunk_overlap_ms.unwrap_or(DEFAULT_CHUNK_OVERLAP_MS);
+    if chunk_ms < MIN_CHUNK_MS {
+        return Err(CommandError::InvalidArgument(format!(
+            "chunkMs must be at least {MIN_CHUNK_MS}."
+        )));
+    }
+    if overlap_ms * 2 >= chunk_ms {
+        return Err(CommandError::InvalidArgument(
+            "chunkOverlapMs must be less than half of chunkMs.".to_string(),
+        ));
+    }
+    let has_extra_outputs = options
+        .output_formats
+        .iter()
+        .flatten()
+        .any(|format| format != "txt");
+    if options.word_timestamps == Some(true)
+        || options.token_probabilities == Some(true)
+        || options.diarize == Some(true)
+        || options.offset_ms.is_some()
+        || options.duration_ms.is_some()
+        || has_extra_outputs
+    {
+        return Err(CommandError::InvalidArgument(
+            "chunkMs can't be combined with wordTimestamps, tokenProbabilities, diarize, offsetMs, durationMs or extra outputFormats.".to_string(),
+        ));
+    }
+    let output_path = resolve_user_path(options.output_path.clone()).map(PathBuf::from);
+    if let Some(output_path) = &output_path {
+        check_output_writable(output_path)?;
+    }
+
+    let mut samples = decode_mono_samples(audio_bytes, options)?;
+    // Normalized once over the whole recording so every window gets the
+    // same gain.
+    if options.normalize_audio == Some(true) {
+        audio::normalize_peak(&mut samples);
+    }
+    let samples_per_ms = (audio::WHISPER_SAMPLE_RATE / 1000) as usize;
+    let total_ms = (samples.len() / samples_per_ms) as u64;
+    let mut windows = Vec::new();
+    let mut start_ms = 0;
+    loop {
+        let end_ms = (start_ms + chunk_ms).min(total_ms);
+        windows.push((start_ms, end_ms));
+        if end_ms >= total_ms {
+            break;
+        }
+        start_ms += chunk_ms - overlap_ms;
+    }
+
+    let chunk_options = WhisperOptions {
+        chunk_ms: None,
+        channel: None,
+        normalize_output: None,
+        normalize_audio: None,
+        output_path: None,
+        audio_format: None,
+        ..options.clone()
+    };
+    // Generous enough for fast speech across the whole overlap.
+    let max_overlap_words = (overlap_ms / 150) as usize + 4;
+    let mut transcript = String::new();
+    let mut processing_ms = 0;
+    for (index, (start_ms, end_ms)) in windows.iter().copied().enumerate() {
+        let window = &samples[start_ms as usize * samples_per_ms..end_ms as usize * samples_per_ms];
+        let text = match whisper_transcribe_once(
+            app,
+            audio::pcm16_wav(window),
+            whisper_binary.clone(),
+            model_path.clone(),
+            language.clone(),
+            &chunk_options,
+        ) {
+            Ok(result) => {
+                processing_ms += result.metrics.processing_ms;
+                result.transcript
+            }
+            Err(CommandError::Whisper(message)) if message == NO_SPEECH_DETECTED => String::new(),
+            Err(error) => {
+                return Err(error.with_detail(&format!(
+                    "chunk {} of {} ({start_ms}-{end_ms} ms)",
+                    index + 1,
+                    windows.len()
+                )))
+            }
+        };
+        transcript = transcript::stitch_overlap(&transcript, &text, max_overlap_words);
+        let _ = app.emit(
+            "whisper:chunk_progress",
+            ChunkProgressPayload {
+                completed: index + 1,
+                total: windows.len(),
+                start_ms,
+                end_ms,
+            },
+        );
+    }
+    if transcript.is_empty() {
+        return Err(CommandError::Whisper(NO_SPEECH_DETECTED.to_string()));
+    }
+    // Normalized once after stitching so chunk boundaries don't start new
+    // sentences.
+    if options.normalize_output.unwrap_or(false) {
+        transcript = transcript::normalize_sentences(&transcript);
+    }
+
+    let metrics = TranscriptMetrics {
+        audio_ms: Some(total_ms),
+        processing_ms,
+        rtf: (total_ms > 0).then(|| processing_ms as f64 / total_ms as f64),
+    };
+    let _ = app.emit("whisper:metrics", metrics.clone());
+    if let Some(output_path) = &output_path {
+        fs::write(output_path, &transcript).map_err(|error| {
+            CommandError::Io(format!(
+                "Failed to write transcript to '{}': {error}",
+                output_path.display()
+            ))
+        })?;
+        transcript = output_path.to_string_lossy().to_string();
+    }
+    Ok(TranscriptResult {
+        transcript,
+        raw_transcript: None,
+        metrics,
+        outputs: BTreeMap::new(),
+        words: Vec::new(),
+        segments: Vec::new(),
+        artifacts_dir: None,
+        input_path: None,
+        confidence: None,
+        translated: None,
+    })
+}
+
+fn whisper_transcribe_local_impl(
+    app: &tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<TranscriptResult> {
+    if options.both == Some(true) {
+        whisper_transcribe_both(
+            app,
+            audio_bytes,
+            whisper_binary,
+            model_path,
+            language,
+            options,
+        )
+    } else {
+        whisper_transcribe_tracked(
+            app,
+            audio_bytes,
+            whisper_binary,
+            model_path,
+            language,
+            options,
+        )
+    }
+}
+
+// What the commands returning a single transcript call, so the post hook
+// runs once on that transcript. Commands that run several transcriptions
+// (ensembles, directory batches) use `whisper_transcribe_local_impl`.
+fn whisper_transcribe_command(
+    app: &tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<TranscriptResult> {
+    let result = whisper_transcribe_local_impl(
+        app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language.clone(),
+        options,
+    )?;
+    run_configured_post_hook(app, &result, language.as_deref(), options);
+    Ok(result)
+}
+
+fn run_configured_post_hook(
+    app: &tauri::AppHandle,
+    result: &TranscriptResult,
+    language: Option<&str>,
+    options: &WhisperOptions,
+) {
+    if options.dry_run.unwrap_or(false) {
+        return;
+    }
+    if let Some(template) = resolve_non_empty(app_config(app).whisper_post_hook)
+        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_POST_HOOK").ok()))
+    {
+        run_post_hook(&template, result, language, options);
+    }
+}
+
+// Runs the post-transcription hook in the background. The template is split
+// on whitespace (no shell is involved) and these placeholders are replaced
+// in each argument: {transcript} (path to a file holding the transcript),
+// {language}, {audioMs} and {processingMs}. Failures are only logged.
+fn run_post_hook(
+    template: &str,
+    result: &TranscriptResult,
+    language: Option<&str>,
+    options: &WhisperOptions,
+) {
+    // With `outputPath` the transcript is already on disk and `transcript`
+    // holds its path; otherwise it goes to a temp file for the hook's
+    // lifetime.
+    let (transcript_path, temp_dir) = if options.output_path.is_some() {
+        (PathBuf::from(&result.transcript), None)
+    } else {
+        let written = create_whisper_working_dir().and_then(|dir| {
+            let path = dir.join("transcript.txt");
+            fs::write(&path, &result.transcript)?;
+            Ok((path, dir))
+        });
+        match written {
+            Ok((path, dir)) => (path, Some(dir)),
+            Err(error) => {
+                tracing::warn!(%error, "skipping post hook: could not write the transcript");
+                return;
+            }
+        }
+    };
+    let placeholders = [
+        (
+            "{transcript}",
+            transcript_path.to_string_lossy().to_string(),
+        ),
+        ("{language}", language.unwrap_or("auto").to_string()),
+        (
+            "{audioMs}",
+            result
+                .metrics
+                .audio_ms
+                .map(|ms| ms.to_string())
+                .unwrap_or_default(),
+        ),
+        ("{processingMs}", result.metrics.processing_ms.to_string()),
+    ];
+    let mut words = template.split_whitespace().map(|word| {
+        placeholders
+            .iter()
+            .fold(word.to_string(), |word, (name, value)| {
+                word.replace(name, value)
+            })
+    });
+    let Some(program) = words.next() else {
+        return;
+    };
+    let mut command = Command::new(&program);
+    command
+        .args(words)
+        .stdin(std::process::Stdio::null())
+        .current_dir(whisper_temp_base());
+    hide_console_window(&mut command);
+    thread::spawn(move || {
+        let command_line = format_command_line(&command);
+        match command.output() {
+            Ok(output) if output.status.success() => {
+                tracing::info!(command = %command_line, "post hook finished");
+            }
+            Ok(output) => tracing::warn!(
+                command = %command_line,
+                status = %format_exit_status(&output.status),
+                stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+                "post hook failed"
+            ),
+            Err(error) => {
+                tracing::warn!(command = %command_line, %error, "post hook could not be launched")
+            }
+        }
+        if let Some(dir) = temp_dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    });
+}
+
+fn whisper_transcribe_tracked(
+    app: &tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<TranscriptResult> {
+    let job_id =
+        resolve_non_empty(options.job_id.clone()).filter(|_| !options.dry_run.unwrap_or(false));
+    let Some(job_id) = job_id else {
+        return whisper_transcribe_once(
+            app,
+            audio_bytes,
+            whisper_binary,
+            model_path,
+            language,
+            options,
+        );
+    };
+    let state = app.state::<WhisperState>();
+    {
+        let mut jobs = lock_recovering(&state.jobs, "whisper jobs");
+        if jobs.contains_key(&job_id) {
+            return Err(CommandError::InvalidArgument(format!(
+                "A whisper job with id '{job_id}' is already running."
+            )));
+        }
+        jobs.insert(job_id.clone(), WhisperJob::default());
+    }
+    let mut language = language;
+    let mut options = WhisperOptions {
+        job_id: Some(job_id.clone()),
+        ..options.clone()
+    };
+    // The audio stays in memory, so a retry re-runs without another upload.
+    loop {
+        let result = whisper_transcribe_once(
+            app,
+            audio_bytes.clone(),
+            whisper_binary.clone(),
+            model_path.clone(),
+            language.clone(),
+            &options,
+        );
+        let mut jobs = lock_recovering(&state.jobs, "whisper jobs");
+        let retry = jobs.get_mut(&job_id).and_then(|job| job.retry.take());
+        let Some(retry) = retry else {
+            jobs.remove(&job_id);
+            return result;
+        };
+        drop(jobs);
+        tracing::info!(job_id = %job_id, "restarting whisper job");
+        let _ = app.emit(
+            "whisper:restarted",
+            WhisperRestartedPayload {
+                job_id: job_id.clone(),
+                language: retry.language.clone(),
+            },
+        );
+        if retry.language.is_some() {
+            language = retry.language;
+        }
+        options = WhisperOptions {
+            job_id: Some(job_id.clone()),
+            keep_input: options.keep_input,
+            prepared_input: options.prepared_input.clone(),
+            ..retry.options
+        };
+    }
+}
+
+// Runs whisper with stdout and stderr captured, recording its pid on the
+// job (if any) so `whisper_retry` can kill it.
+fn run_whisper_process(
+    app: &tauri::AppHandle,
+    job_id: Option<&str>,
+    command: &mut Command,
+) -> std::io::Result<std::process::Output> {
+    let child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let state = app.state::<WhisperState>();
+    let set_pid = |pid: Option<u32>| {
+        if let Some(id) = job_id {
+            if let Some(job) = lock_recovering(&state.jobs, "whisper jobs").get_mut(id) {
+                job.pid = pid;
+            }
+        }
+    };
+    set_pid(Some(child.id()));
+    let output = child.wait_with_output();
+    set_pid(None);
+    output
+}
+
+// Directory names (e.g. a user or client name) that shouldn't appear in
+// failure records; matched case-insensitively against path components.
+fn whisper_redact_dirs(app: &tauri::AppHandle) -> Vec<String> {
+    app_config(app)
+        .whisper_redact_dirs
+        .or_else(|| {
+            std::env::var("GREEPY_WHISPER_REDACT_DIRS")
+                .ok()
+                .map(|value| value.split(',').map(str::to_string).collect())
+        })
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn passes_through_redacted_dir(path: &Path, redact_dirs: &[String]) -> bool {
+    path.components().any(|component| {
+        let component = component.as_os_str().to_string_lossy();
+        redact_dirs
+            .iter()
+            .any(|name| component.eq_ignore_ascii_case(name))
+    })
+}
+
+// Keeps a failed run for `whisper_last_errors`. Every path the run was given
+// (the binary, the model, the audio input, the working directory) that runs
+// through one of the redacted directories is replaced throughout, and the
+// home directory becomes `~` in what's left.
+fn record_whisper_failure(
+    app: &tauri::AppHandle,
+    command: &Command,
+    status: String,
+    output: Option<&std::process::Output>,
+    working_dir: &Path,
+) {
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_string();
+    let mut failure = WhisperFailure {
+        failed_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default(),
+        command_line: format_command_line(command),
+        status,
+        stdout: output
+            .map(|output| text(&output.stdout))
+            .unwrap_or_default(),
+        stderr: output
+            .map(|output| text(&output.stderr))
+            .unwrap_or_default(),
+    };
+    let redact_dirs = whisper_redact_dirs(app);
+    let mut redacted: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(Path::new)
+        .filter(|path| path.is_absolute())
+        .chain(std::iter::once(working_dir))
+        .filter(|path| passes_through_redacted_dir(path, &redact_dirs))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    // Longest first, so a file inside the working directory goes as a whole
+    // rather than leaving its name behind.
+    redacted.sort_by_key(|path| std::cmp::Reverse(path.len()));
+    let home = paths::home_dir()
+        .map(|home| home.to_string_lossy().to_string())
+        .filter(|home| home.len() > 1);
+    for field in [
+        &mut failure.command_line,
+        &mut failure.stdout,
+        &mut failure.stderr,
+    ] {
+        for path in &redacted {
+            *field = field.replace(path.as_str(), "<redacted>");
+        }
+        if let Some(home) = &home {
+            *field = field.replace(home.as_str(), "~");
+        }
+    }
+    let state = app.state::<WhisperState>();
+    let mut failures = lock_recovering(&state.failures, "whisper failures");
+    if failures.len() == WHISPER_FAILURE_HISTORY {
+        failures.pop_front();
+    }
+    failures.push_back(failure);
+}
+
+// Newest first, for attaching to bug reports.
+#[tauri::command]
+fn whisper_last_errors(app: tauri::AppHandle, n: Option<usize>) -> Vec<WhisperFailure> {
+    let state = app.state::<WhisperState>();
+    let failures = lock_recovering(&state.failures, "whisper failures");
+    failures
+        .iter()
+        .rev()
+        .take(n.unwrap_or(WHISPER_FAILURE_HISTORY))
+        .cloned()
+        .collect()
+}
+
+// A retry was requested for the job, so its current run is being abandoned.
+fn whisper_retry_pending(app: &tauri::AppHandle, job_id: Option<&str>) -> bool {
+    let state = app.state::<WhisperState>();
+    job_id
+        .and_then(|id| {
+            lock_recovering(&state.jobs, "whisper jobs")
+                .get(id)
+                .map(|job| job.retry.is_some())
+        })
+        .unwrap_or(false)
+}
+
+struct PreparedAudio {
+    bytes: Vec<u8>,
+    wav_info: Option<audio::WavInfo>,
+    container: Option<&'static str>,
+    // A channel ffmpeg still has to pick during conversion.
+    source_channel: Option<u16>,
+    needs_conversion: bool,
+}
+
+// Checks the audio against the channel and diarize options and applies what
+// can be done in Rust: channel extraction and normalizing 16 kHz PCM.
+fn prepare_audio_bytes(
+    audio_bytes: Vec<u8>,
+    options: &WhisperOptions,
+    diarize_flag: Option<&str>,
+    target_channels: u16,
+) -> CommandResult<PreparedAudio> {
+    let source_channel = options.channel.and_then(ChannelSelect::index);
+    if let Some(channel) = source_channel {
+        if diarize_flag == Some("--diarize") {
+            return Err(CommandError::InvalidArgument(
+                "channel can't select a single channel when diarize needs both.".to_string(),
+            ));
+        }
+        let channels = audio::parse_wav_header(&audio_bytes)
+            .map(|info| info.channels)
+            .or_else(|| options.audio_format.as_ref().and_then(|hint| hint.channels));
+        if let Some(channels) = channels.filter(|channels| channel >= *channels) {
+            return Err(CommandError::InvalidArgument(format!(
+                "channel 'right' needs stereo input, but the audio has {channels} channel(s)."
+            )));
+        }
+    }
+    // Picking a channel out of 16 kHz PCM is done here; anything else goes
+    // through ffmpeg's pan filter during conversion.
+    let audio_bytes = match source_channel
+        .and_then(|channel| audio::extract_pcm16_channel(&audio_bytes, channel))
+    {
+        Some(extracted) => extracted,
+        None => audio_bytes,
+    };
+    let wav_info = audio::parse_wav_header(&audio_bytes);
+    if diarize_flag == Some("--diarize") {
+        if let Some(channels) = wav_info.as_ref().map(|info| info.channels) {
+            if channels != 2 {
+                return Err(CommandError::InvalidArgument(format!(
+                    "diarize needs stereo audio with one speaker per channel (got {channels} channel(s)), or a tinydiarize (tdrz) model."
+                )));
+            }
+        }
+    }
+    let container = audio::sniff_container(&audio_bytes);
+    // A channel that wasn't extracted above still has to be picked by ffmpeg.
+    let source_channel =
+        source_channel.filter(|_| wav_info.as_ref().map(|info| info.channels) != Some(1));
+    let needs_conversion = audio_needs_conversion(
+        wav_info.as_ref(),
+        container,
+        options.audio_format.as_ref(),
+        target_channels,
+    )? || source_channel.is_some();
+    // Converted audio is normalized by ffmpeg instead.
+    let audio_bytes = match (options.normalize_audio == Some(true) && !needs_conversion)
+        .then(|| audio::normalize_pcm16_wav(&audio_bytes))
+        .flatten()
+    {
+        Some(normalized) => normalized,
+        None => audio_bytes,
+    };
+    Ok(PreparedAudio {
+        bytes: audio_bytes,
+        wav_info,
+        container,
+        source_channel,
+        needs_conversion,
+    })
+}
+
+fn whisper_transcribe_once(
+    app: &tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<TranscriptResult> {
+    if audio_bytes.is_empty() && options.prepared_input.is_none() {
+        return Err(CommandError::InvalidArgument(
+            "No audio payload received.".to_string(),
+        ));
+    }
+    check_audio_size(audio_bytes.len() as u64, max_audio_bytes(app))?;
+    if let Some(chunk_ms) = options
+        .chunk_ms
+        .filter(|_| !options.dry_run.unwrap_or(false))
+    {
+        return whisper_transcribe_chunked(
+            app,
+            &audio_bytes,
+            whisper_binary,
+            model_path,
+            language,
+            options,
+            chunk_ms,
+        );
+    }
+    validate_bounded_option("beamSize", options.beam_size, MAX_WHISPER_BEAM_SIZE)?;
+    validate_bounded_option("bestOf", options.best_of, MAX_WHISPER_BEST_OF)?;
+    if let Some(token) = options
+        .suppress_tokens
+        .iter()
+        .flatten()
+        .find(|token| **token < 0)
+    {
+        return Err(CommandError::InvalidArgument(format!(
+            "suppressTokens must be non-negative token ids, got {token}."
+        )));
+    }
+    // Unsigned types already reject negative values during deserialization; a
+    // zero duration would make whisper.cpp silently process the whole file.
+    if options.duration_ms == Some(0) {
+        return Err(CommandError::InvalidArgument(
+            "durationMs must be greater than zero.".to_string(),
+        ));
+    }
+    validate_bounded_option("maxLen", options.max_len, MAX_WHISPER_SEGMENT_LEN)?;
+    if let Some(min_confidence) = options.min_confidence {
+        if !(0.0..=1.0).contains(&min_confidence) {
+            return Err(CommandError::InvalidArgument(format!(
+                "minConfidence must be between 0 and 1 (got {min_confidence})."
+            )));
+        }
+    }
+    if let Some(max_context) = options.max_context {
+        if !(-1..=MAX_WHISPER_CONTEXT_TOKENS).contains(&max_context) {
+            return Err(CommandError::InvalidArgument(format!(
+                "maxContext must be between -1 and {MAX_WHISPER_CONTEXT_TOKENS} (got {max_context})."
+            )));
+        }
+    }
+    if options.num_speakers == Some(0) {
+        return Err(CommandError::InvalidArgument(
+            "numSpeakers must be at least 1.".to_string(),
+        ));
+    }
+    let output_formats = resolve_output_formats(options.output_formats.as_ref())?;
+    if options.split_on_word == Some(true)
+        && !output_formats
+            .iter()
+            .any(|format| matches!(*format, "srt" | "vtt"))
+    {
+        return Err(CommandError::InvalidArgument(
+            "splitOnWord only affects subtitle cues; add \"srt\" or \"vtt\" to outputFormats."
+                .to_string(),
+        ));
+    }
+    let extra_args = options.extra_args.clone().unwrap_or_default();
+    validate_extra_args(&extra_args)?;
+
+    let resolved_binary = resolve_whisper_binary(app, whisper_binary);
+    let resolved_model_path = resolve_whisper_model_path(app, model_path)?;
+    let diarize_flag = options.diarize.unwrap_or(false).then(|| {
+        if is_tinydiarize_model_path(&resolved_model_path) {
+            "--tinydiarize"
+        } else {
+            "--diarize"
+        }
+    });
+    let mut optional_flags = optional_whisper_flags(options, &output_formats);
+    if let Some(flag) = diarize_flag {
+        optional_flags.extend([flag, "--output-json"]);
+    }
+    if !optional_flags.is_empty() {
+        // A binary that can't be probed fails later with the usual launch error.
+        if let Ok(capabilities) = cached_whisper_capabilities(app, &resolved_binary) {
+            for flag in optional_flags {
+                capabilities.require(flag)?;
+            }
+        }
+    }
+    // Unlike the flags above the speaker count is only a hint, so a build
+    // without it (or one that can't be probed) still transcribes.
+    let num_speakers = options.num_speakers.and_then(|count| {
+        let ignored = if diarize_flag.is_none() {
+            Some("numSpeakers only applies with diarize; ignoring it.".to_string())
+        } else if !cached_whisper_capabilities(app, &resolved_binary)
+            .is_ok_and(|capabilities| capabilities.require("--num-speakers").is_ok())
+        {
+            Some("This whisper build does not accept --num-speakers; ignoring numSpeakers.".to_string())
+        } else {
+            None
+        };
+        if let Some(message) = ignored {
+            let _ = app.emit("whisper:warning", WhisperWarningPayload { message });
+            return None;
+        }
+        if count > MAX_EXPECTED_SPEAKERS {
+            let _ = app.emit(
+                "whisper:warning",
+                WhisperWarningPayload {
+                    message: format!(
+                        "numSpeakers is {count}; diarization is rarely reliable beyond {MAX_EXPECTED_SPEAKERS} speakers."
+                    ),
+                },
+            );
+        }
+        Some(count)
+    });
+
+    let resolved_language = resolve_non_empty(language)
+        .or_else(|| resolve_non_empty(app_config(app).whisper_language))
+        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_LANGUAGE").ok()))
+        .unwrap_or_else(|| "auto".to_string());
+    let resolved_language = languages::resolve_language(&resolved_language)?;
+    let model_info = whisper_model_info(Path::new(&resolved_model_path));
+    if model_info.is_english_only && !matches!(resolved_language.as_str(), "en" | "auto") {
+        let _ = app.emit(
+            "whisper:warning",
+            WhisperWarningPayload {
+                message: format!(
+                    "{} is an English-only model; language '{resolved_language}' will not be transcribed correctly.",
+                    model_info.file_name
+                ),
+            },
+        );
+    }
+    if model_info.is_english_only && options.translate == Some(true) {
+        let _ = app.emit(
+            "whisper:warning",
+            WhisperWarningPayload {
+                message: format!(
+                    "{} is an English-only model and can't translate; use a multilingual model.",
+                    model_info.file_name
+                ),
+            },
+        );
+    }
+
+    let target_channels = if diarize_flag == Some("--diarize") {
+        2
+    } else {
+        1
+    };
+    let normalize_audio = options.normalize_audio == Some(true);
+    let prepared_input = options.prepared_input.clone();
+    // A prepared input already had its channel picked, level raised and
+    // format converted by an earlier pass.
+    let PreparedAudio {
+        bytes: audio_bytes,
+        wav_info,
+        container,
+        source_channel,
+        needs_conversion,
+    } = match &prepared_input {
+        Some(path) => PreparedAudio {
+            bytes: Vec::new(),
+            wav_info: audio::read_wav_file_info(path),
+            container: None,
+            source_channel: None,
+            needs_conversion: false,
+        },
+        None => prepare_audio_bytes(audio_bytes, options, diarize_flag, target_channels)?,
+    };
+    // Named in conversion errors so users see what the file really was.
+    let detected_format = match (&wav_info, container) {
+        (Some(info), _) => info.describe(),
+        (None, Some(container)) => container.to_string(),
+        (None, None) => "unknown".to_string(),
+    };
+    let mut audio_ms = wav_info.and_then(|info| info.duration_ms());
+    // Only named here; nothing is created on disk until the dry-run check.
+    let working_dir = whisper_working_dir_path();
+    let input_audio_path = prepared_input
+        .clone()
+        .unwrap_or_else(|| working_dir.join("input.wav"));
+    let output_base_path = working_dir.join("transcript");
+
+    let mut whisper_command = Command::new(&resolved_binary);
+    whisper_command
+        .arg("-m")
+        .arg(&resolved_model_path)
+        .arg("-f")
+        .arg(&input_audio_path)
+        .arg("-l")
+        .arg(&resolved_language)
+        .arg("-of")
+        .arg(&output_base_path);
+    for format in &output_formats {
+        whisper_command.arg(output_format_flag(format));
+    }
+    let word_timestamps = options.word_timestamps.unwrap_or(false);
+    let token_probabilities = options.token_probabilities.unwrap_or(false);
+    let full_json = word_timestamps || token_probabilities || options.min_confidence.is_some();
+    if full_json {
+        whisper_command.arg("--output-json-full");
+    }
+    if let Some(flag) = diarize_flag {
+        whisper_command.arg(flag).arg("--output-json");
+    }
+    if let Some(count) = num_speakers {
+        whisper_command.arg("--num-speakers").arg(count.to_string());
+    }
+    if let Some(max_len) = options.max_len {
+        whisper_command.arg("--max-len").arg(max_len.to_string());
+    }
+    if options.split_on_word == Some(true) {
+        whisper_command.arg("--split-on-word");
+    }
+    if options.translate == Some(true) {
+        whisper_command.arg("--translate");
+    }
+    if let Some(max_context) = options.max_context {
+        whisper_command
+            .arg("--max-context")
+            .arg(max_context.to_string());
+    }
+    if !options.use_gpu.unwrap_or(true) {
+        whisper_command.arg("--no-gpu");
+    }
+    if options.suppress_non_speech == Some(true) {
+        whisper_command.arg("--suppress-nst");
+    }
+    if let Some(tokens) = options
+        .suppress_tokens
+        .as_ref()
+        .filter(|tokens| !tokens.is_empty())
+    {
+        let joined = tokens
+            .iter()
+            .map(|token| token.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        whisper_command.arg("--suppress-tokens").arg(joined);
+    }
+    if let Some(beam_size) = options.beam_size {
+        whisper_command
+            .arg("--beam-size")
+            .arg(beam_size.to_string());
+    }
+    if let Some(best_of) = options.best_of {
+        whisper_command.arg("--best-of").arg(best_of.to_string());
+    }
+    if let Some(offset_ms) = options.offset_ms {
+        whisper_command.arg("--offset-t").arg(offset_ms.to_string());
+    }
+    if let Some(duration_ms) = options.duration_ms {
+        whisper_command
+            .arg("--duration")
+            .arg(duration_ms.to_string());
+    }
+    if let Some(prompt) = resolve_non_empty(options.initial_prompt.clone()) {
+        let truncated = truncate_prompt_tail(&prompt, MAX_WHISPER_PROMPT_CHARS);
+        if truncated.len() < prompt.len() {
+            let _ = app.emit(
+                "whisper:warning",
+                WhisperWarningPayload {
+                    message: format!(
+                        "Initial prompt truncated to its last {MAX_WHISPER_PROMPT_CHARS} characters."
+                    ),
+                },
+            );
+        }
+        whisper_command.arg("--prompt").arg(truncated);
+    }
+    whisper_command.args(&extra_args);
+    if options.dry_run.unwrap_or(false) {
+        return Ok(TranscriptResult {
+            transcript: format_command_line(&whisper_command),
+            raw_transcript: None,
+            metrics: TranscriptMetrics {
+                audio_ms,
+                processing_ms: 0,
+                rtf: None,
+            },
+            outputs: BTreeMap::new(),
+            words: Vec::new(),
+            segments: Vec::new(),
+            artifacts_dir: None,
+            input_path: None,
+            confidence: None,
+            translated: None,
+        });
+    }
+    let output_path = resolve_user_path(options.output_path.clone()).map(PathBuf::from);
+    if let Some(output_path) = &output_path {
+        check_output_writable(output_path)?;
+    }
+    // The source as written, plus the 16-bit PCM it converts to: 32 bytes
+    // per millisecond and channel, or a generous guess for compressed input
+    // of unknown length.
+    let source_len = audio_bytes.len() as u64;
+    let converted_len = match (needs_conversion, audio_ms) {
+        (false, _) => 0,
+        (true, Some(ms)) => ms * 32 * u64::from(target_channels),
+        (true, None) => source_len * 12,
+    };
+    check_whisper_disk_space(
+        &working_dir,
+        source_len + converted_len + WHISPER_DISK_MARGIN_BYTES,
+    )?;
+    create_fresh_dir(&working_dir)?;
+    // Whisper always reads 16 kHz WAV; only the pre-conversion source keeps
+    // the input's own format, named after what its header says it is.
+    if needs_conversion {
+        let extension = container
+            .map(str::to_string)
+            .or_else(|| {
+                options
+                    .audio_format
+                    .as_ref()
+                    .and_then(|format| resolve_non_empty(format.codec.clone()))
+            })
+            .unwrap_or_else(|| "audio".to_string());
+        let source_path =
+            write_whisper_input(&working_dir, &format!("source.{extension}"), &audio_bytes)?;
+        audio::convert_with_ffmpeg(
+            &source_path,
+            &input_audio_path,
+            target_channels,
+            source_channel,
+            normalize_audio,
+        )
+        .map_err(|error| {
+            let _ = fs::remove_dir_all(&working_dir);
+            error.with_detail(&format!("detected input: {detected_format}"))
+        })?;
+        if audio_ms.is_none() {
+            audio_ms = fs::read(&input_audio_path)
+                .ok()
+                .and_then(|converted| audio::parse_wav_header(&converted))
+                .and_then(|info| info.duration_ms());
+        }
+    } else if prepared_input.is_none() {
+        write_whisper_input(&working_dir, "input.wav", &audio_bytes)?;
+    }
+    let keep_artifacts = options.keep_artifacts.unwrap_or(false)
+        || std::env::var("GREEPY_WHISPER_KEEP")
+            .map(|value| !value.is_empty() && value != "0")
+            .unwrap_or(false);
+    // Cleans up before an error is returned; kept artifacts are named in the
+    // error instead.
+    let fail = |error: CommandError| {
+        if keep_artifacts {
+            error.with_detail(&format!("artifacts: {}", working_dir.display()))
+        } else {
+            let _ = fs::remove_dir_all(&working_dir);
+            error
+        }
+    };
+    hide_console_window(&mut whisper_command);
+    let whisper_state = app.state::<WhisperState>();
+    let _permit = whisper_state.queue.acquire();
+    let _span = tracing::info_span!("whisper_transcribe").entered();
+    let job_id = options.job_id.as_deref();
+    let mut current_model = resolved_model_path.clone();
+    let mut fallbacks = 0;
+    let (process_output, processing_ms) = loop {
+        let started_at = Instant::now();
+        tracing::info!(command = %format_command_line(&whisper_command), "running whisper");
+        let process_output =
+            run_whisper_process(app, job_id, &mut whisper_command).map_err(|error| {
+                tracing::error!(%error, "failed to launch whisper");
+                record_whisper_failure(
+                    app,
+                    &whisper_command,
+                    format!("failed to launch: {error}"),
+                    None,
+                    &working_dir,
+                );
+                fail(CommandError::SpawnFailed(format!(
+                    "Failed to launch whisper binary '{resolved_binary}': {error}"
+                )))
+            })?;
+        let processing_ms = started_at.elapsed().as_millis() as u64;
+        tracing::info!(
+            status = %format_exit_status(&process_output.status),
+            processing_ms,
+            "whisper exited"
+        );
+        if !process_output.status.success() {
+            record_whisper_failure(
+                app,
+                &whisper_command,
+                format_exit_status(&process_output.status),
+                Some(&process_output),
+                &working_dir,
+            );
+        }
+        // A non-zero exit after a successful launch is usually the model
+        // failing to load (out of memory); a smaller one may still fit.
+        let fallback = (!process_output.status.success()
+            && options.model_fallback.unwrap_or(false)
+            && fallbacks < MAX_MODEL_FALLBACKS
+            && !whisper_retry_pending(app, job_id))
+        .then(|| find_smaller_whisper_model(app, &current_model))
+        .flatten();
+        let Some(smaller_model) = fallback else {
+            break (process_output, processing_ms);
+        };
+        let _ = app.emit(
+            "whisper:fallback",
+            WhisperFallbackPayload {
+                from_model: current_model.clone(),
+                to_model: smaller_model.clone(),
+                reason: format_exit_status(&process_output.status),
+            },
+        );
+        whisper_command = with_whisper_model(&whisper_command, &smaller_model);
+        current_model = smaller_model;
+        fallbacks += 1;
+    };
+
+    if !process_output.status.success() {
+        let stderr = String::from_utf8_lossy(&process_output.stderr)
+            .trim()
+            .to_string();
+        let stdout = String::from_utf8_lossy(&process_output.stdout)
+            .trim()
+            .to_string();
+        let mut details = Vec::new();
+        details.push(format!(
+            "status: {}",
+            format_exit_status(&process_output.status)
+        ));
+        if !stderr.is_empty() {
+            details.push(format!("stderr: {stderr}"));
+        }
+        if !stdout.is_empty() {
+            details.push(format!("stdout: {stdout}"));
+        }
+        if stderr.is_empty() && stdout.is_empty() {
+            details.push("No process output captured.".to_string());
+        }
+        details.push(format!("binary: {resolved_binary}"));
+        details.push(format!("model: {current_model}"));
+        if let Some(hint) = whisper_runtime_hint(process_output.status.code()) {
+            details.push(hint.to_string());
+        }
+        return Err(fail(CommandError::Whisper(format!(
+            "Whisper transcription failed: {}",
+            details.join(" | ")
+        ))));
+    }
+
+    let transcript_file = output_base_path.with_extension("txt");
+    let transcript = fs::read_to_string(&transcript_file).map_err(|error| {
+        fail(CommandError::Io(format!(
+            "Failed to read transcript output: {error}"
+        )))
+    })?;
+    let mut outputs = BTreeMap::new();
+    for format in output_formats.iter().filter(|format| **format != "txt") {
+        let output_file = output_base_path.with_extension(format);
+        let contents = fs::read_to_string(&output_file).map_err(|error| {
+            fail(CommandError::Io(format!(
+                "Failed to read {format} output: {error}"
+            )))
+        })?;
+        outputs.insert(format.to_string(), contents);
+    }
+    let json_output = if full_json || diarize_flag.is_some() {
+        let json_file = output_base_path.with_extension("json");
+        Some(fs::read_to_string(&json_file).map_err(|error| {
+            fail(CommandError::Io(format!(
+                "Failed to read JSON output: {error}"
+            )))
+        })?)
+    } else {
+        None
+    };
+    let words = match &json_output {
+        Some(contents) if word_timestamps => parse_word_timings(contents),
+        _ => Vec::new(),
+    };
+    let segments = match &json_output {
+        Some(contents) if diarize_flag.is_some() || token_probabilities => parse_segments(
+            contents,
+            diarize_flag.map(|flag| flag == "--tinydiarize"),
+            token_probabilities,
+        ),
+        _ => Vec::new(),
+    };
+    let confidence = json_output
+        .as_deref()
+        .filter(|_| full_json)
+        .and_then(transcript_confidence);
+    if let (Some(confidence), Some(threshold)) = (confidence, options.min_confidence) {
+        if confidence < threshold {
+            let _ = app.emit(
+                "whisper:low_confidence",
+                LowConfidencePayload {
+                    confidence,
+                    threshold,
+                },
+            );
+        }
+    }
+
+    let (artifacts_dir, input_path) = if keep_artifacts || options.keep_input {
+        (
+            Some(working_dir.to_string_lossy().to_string()),
+            Some(input_audio_path.to_string_lossy().to_string()),
+        )
+    } else {
+        let _ = fs::remove_dir_all(&working_dir);
+        (None, None)
+    };
+    let mut raw_transcript = None;
+    let mut cleaned = if diarize_flag.is_none() || segments.is_empty() {
+        transcript.trim().to_string()
+    } else {
+        label_speakers(&segments)
+    };
+    if options.strip_annotations.unwrap_or(false) {
+        let stripped = transcript::strip_annotations(&cleaned);
+        if stripped != cleaned {
+            raw_transcript = Some(std::mem::replace(&mut cleaned, stripped));
+        }
+    }
+    if options.normalize_output.unwrap_or(false) {
+        let normalized = transcript::normalize_sentences(&cleaned);
+        if normalized != cleaned {
+            let previous = std::mem::replace(&mut cleaned, normalized);
+            raw_transcript.get_or_insert(previous);
+        }
+    }
+    if cleaned.is_empty() {
+        return Err(fail(CommandError::Whisper(NO_SPEECH_DETECTED.to_string())));
+    }
+
+    let metrics = TranscriptMetrics {
+        audio_ms,
+        processing_ms,
+        rtf: audio_ms
+            .filter(|audio_ms| *audio_ms > 0)
+            .map(|audio_ms| processing_ms as f64 / audio_ms as f64),
+    };
+    let _ = app.emit("whisper:metrics", metrics.clone());
+
+    if let Some(output_path) = &output_path {
+        fs::write(output_path, &cleaned).map_err(|error| {
+            CommandError::Io(format!(
+                "Failed to write transcript to '{}': {error}",
+                output_path.display()
+            ))
+        })?;
+        for (format, contents) in outputs.iter_mut() {
+            let format_path = output_path.with_extension(format);
+            fs::write(&format_path, contents.as_bytes()).map_err(|error| {
+                CommandError::Io(format!(
+                    "Failed to write {format} output to '{}': {error}",
+                    format_path.display()
+                ))
+            })?;
+            *contents = format_path.to_string_lossy().to_string();
+        }
+        cleaned = output_path.to_string_lossy().to_string();
+        raw_transcript = None;
+    }
+
+    Ok(TranscriptResult {
+        transcript: cleaned,
+        raw_transcript,
+        metrics,
+        outputs,
+        words,
+        segments,
+        artifacts_dir,
+        input_path,
+        confidence,
+        translated: None,
+    })
+}
+
+// Case-insensitive `*` / `?` matching against a file name.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|candidate| *candidate == '*')
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchProgressPayload {
+    path: String,
+    completed: usize,
+    total: usize,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchFailure {
+    path: String,
+    error: String,
+}
+
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct BatchSummary {
+    // Transcript files written next to their source audio.
+    succeeded: Vec<String>,
+    failed: Vec<BatchFailure>,
+}
+
+fn transcribe_to_sibling_file(
+    app: &tauri::AppHandle,
+    audio_path: &Path,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<String> {
+    let audio_bytes = read_audio_file(app, &audio_path.to_string_lossy())?;
+    // With `outputPath` the result would hold that path instead of the text,
+    // and every file would overwrite the same output.
+    let options = WhisperOptions {
+        output_path: None,
+        ..options.clone()
+    };
+    let result = whisper_transcribe_local_impl(
+        app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options,
+    )?;
+    let transcript_path = audio_path.with_extension("txt");
+    fs::write(&transcript_path, &result.transcript).map_err(|error| {
+        CommandError::Io(format!(
+            "Failed to write transcript '{}': {error}",
+            transcript_path.display()
+        ))
+    })?;
+    Ok(transcript_path.to_string_lossy().to_string())
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_directory(
+    app: tauri::AppHandle,
+    dir: String,
+    pattern: Option<String>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<BatchSummary> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let pattern = resolve_non_empty(pattern).unwrap_or_else(|| "*.wav".to_string());
+    let entries = fs::read_dir(dir.trim()).map_err(|error| {
+        CommandError::Io(format!(
+            "Failed to read directory '{}': {error}",
+            dir.trim()
+        ))
+    })?;
+    let mut audio_paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|candidate| candidate.to_str())
+                .map(|candidate| wildcard_match(&pattern, candidate))
+                .unwrap_or(false)
+        })
+        .collect();
+    audio_paths.sort();
+
+    let total = audio_paths.len();
+    let options = options.unwrap_or_default();
+    let pending = Arc::new(Mutex::new(audio_paths.into_iter().collect::<VecDeque<_>>()));
+    let summary = Arc::new(Mutex::new(BatchSummary::default()));
+    let worker_count = app.state::<WhisperState>().queue.limit.min(total);
+
+    let workers: Vec<_> = (0..worker_count)
+        .map(|_| {
+            let app = app.clone();
+            let pending = pending.clone();
+            let summary = summary.clone();
+            let whisper_binary = whisper_binary.clone();
+            let model_path = model_path.clone();
+            let language = language.clone();
+            let options = options.clone();
+            thread::spawn(move || loop {
+                let Some(audio_path) = lock_recovering(&pending, "batch queue").pop_front() else {
+                    break;
+                };
+                let outcome = transcribe_to_sibling_file(
+                    &app,
+                    &audio_path,
+                    whisper_binary.clone(),
+                    model_path.clone(),
+                    language.clone(),
+                    &options,
+                );
+                let path = audio_path.to_string_lossy().to_string();
+                let mut summary = lock_recovering(&summary, "batch summary");
+                let error = match outcome {
+                    Ok(transcript_path) => {
+                        summary.succeeded.push(transcript_path);
+                        None
+                    }
+                    Err(error) => {
+                        summary.failed.push(BatchFailure {
+                            path: path.clone(),
+                            error: error.to_string(),
+                        });
+                        Some(error.to_string())
+                    }
+                };
+                let completed = summary.succeeded.len() + summary.failed.len();
+                drop(summary);
+                let _ = app.emit(
+                    "whisper:batch_progress",
+                    BatchProgressPayload {
+                        path,
+                        completed,
+                        total,
+                        error,
+                    },
+                );
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let summary = lock_recovering(&summary, "batch summary").clone();
+    Ok(summary)
+}
+
+fn read_audio_file(app: &tauri::AppHandle, audio_path: &str) -> CommandResult<Vec<u8>> {
+    let expanded_audio_path = paths::expand_path(audio_path.trim());
+    let trimmed_audio_path = expanded_audio_path.as_str();
+    if trimmed_audio_path.is_empty() {
+        return Err(CommandError::InvalidArgument(
+            "Audio file path is missing.".to_string(),
+        ));
+    }
+    // Check the size on disk first so an oversized file is never buffered.
+    let metadata = fs::metadata(trimmed_audio_path).map_err(|error| {
+        CommandError::Io(format!(
+            "Failed to read audio file '{trimmed_audio_path}': {error}"
+        ))
+    })?;
+    check_audio_size(metadata.len(), max_audio_bytes(app))?;
+    fs::read(trimmed_audio_path).map_err(|error| {
+        CommandError::Io(format!(
+            "Failed to read audio file '{trimmed_audio_path}': {error}"
+        ))
+    })
+}
+
+const MAX_FILE_CHUNK_LEN: u64 = 4 * 1024 * 1024;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FileChunk {
+    data: Vec<u8>,
+    offset: u64,
+    total_size: u64,
+    eof: bool,
+}
+
+// Returns up to `len` bytes of a file starting at `offset`, so large outputs
+// such as transcripts written with `outputPath` can be paged through instead
+// of crossing IPC in one payload.
+#[tauri::command]
+fn read_file_chunked(path: String, offset: u64, len: u64) -> CommandResult<FileChunk> {
+    let path = resolve_user_path(Some(path))
+        .ok_or_else(|| CommandError::InvalidArgument("File path is missing.".to_string()))?;
+    if len == 0 || len > MAX_FILE_CHUNK_LEN {
+        return Err(CommandError::InvalidArgument(format!(
+            "len must be between 1 and {MAX_FILE_CHUNK_LEN}."
+        )));
+    }
+    let mut file = fs::File::open(&path)
+        .map_err(|error| CommandError::Io(format!("Failed to open '{path}': {error}")))?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(CommandError::InvalidArgument(format!(
+            "'{path}' is not a file."
+        )));
+    }
+    let total_size = metadata.len();
+    if offset > total_size {
+        return Err(CommandError::InvalidArgument(format!(
+            "offset {offset} is past the end of '{path}' ({total_size} bytes)."
+        )));
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len.min(total_size - offset) as usize);
+    file.take(len).read_to_end(&mut data)?;
+    Ok(FileChunk {
+        eof: offset + data.len() as u64 >= total_size,
+        data,
+        offset,
+        total_size,
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiskSpace {
+    mount_point: String,
+    available_bytes: u64,
+    total_bytes: u64,
+}
+
+// Space on the volume holding `path`, which doesn't have to exist yet: its
+// nearest existing ancestor decides the volume. `None` when no mounted disk
+// contains it (e.g. some network shares).
+fn disk_space_for(path: &Path) -> Option<DiskSpace> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let path = fs::canonicalize(existing).ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| DiskSpace {
+            mount_point: disk.mount_point().to_string_lossy().to_string(),
+            available_bytes: disk.available_space(),
+            total_bytes: disk.total_space(),
+        })
+}
+
+#[tauri::command]
+fn disk_space(path: String) -> CommandResult<Option<DiskSpace>> {
+    let path = resolve_user_path(Some(path))
+        .ok_or_else(|| CommandError::InvalidArgument("Path is missing.".to_string()))?;
+    Ok(disk_space_for(Path::new(&path)))
+}
+
+// Room for raw whisper output, logs and filesystem rounding on top of the
+// audio itself.
+const WHISPER_DISK_MARGIN_BYTES: u64 = 16 * 1024 * 1024;
+
+// Fails early when the temp volume clearly can't hold the transcription's
+// files, rather than partway through a long run. Skipped when the free space
+// can't be determined.
+fn check_whisper_disk_space(dir: &Path, required: u64) -> CommandResult<()> {
+    let Some(space) = disk_space_for(dir) else {
+        return Ok(());
+    };
+    if space.available_bytes >= required {
+        return Ok(());
+    }
+    const MIB: u64 = 1024 * 1024;
+    Err(CommandError::Io(format!(
+        "Not enough disk space for transcription in {}: about {} MiB is needed but only {} MiB is free on {}. Free up space or point GREEPY_WHISPER_TMP at another drive.",
+        dir.display(),
+        required.div_ceil(MIB),
+        space.available_bytes / MIB,
+        space.mount_point
+    )))
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_local(
+    app: tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<String> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+    .map(|result| result.transcript)
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_local_file(
+    app: tauri::AppHandle,
+    audio_path: String,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<String> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let audio_bytes = read_audio_file(&app, &audio_path)?;
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+    .map(|result| result.transcript)
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_local_detailed(
+    app: tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<TranscriptResult> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_local_file_detailed(
+    app: tauri::AppHandle,
+    audio_path: String,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<TranscriptResult> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let audio_bytes = read_audio_file(&app, &audio_path)?;
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+}
+
+// Upload directories share the working-dir prefix so the startup sweep also
+// removes ones left behind by a crash mid-upload.
+fn create_upload_dir() -> CommandResult<PathBuf> {
+    static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    let dir = whisper_temp_base().join(format!(
+        "{WHISPER_TEMP_DIR_PREFIX}upload-{stamp}-{}",
+        NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Appends `chunk` to the job's temp file and, once `is_final` is set, runs a
+// single transcription over the assembled audio. Returns `None` for every
+// chunk but the last, so long recordings never cross IPC as one payload.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command(async)]
+fn whisper_transcribe_chunks(
+    app: tauri::AppHandle,
+    job_id: String,
+    chunk: Vec<u8>,
+    is_final: bool,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<Option<TranscriptResult>> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let state = app.state::<WhisperState>();
+    let mut uploads = lock_recovering(&state.uploads, "whisper uploads");
+    if !uploads.contains_key(&job_id) {
+        let dir = create_upload_dir()?;
+        let file = match fs::File::create(dir.join("upload.bin")) {
+            Ok(file) => file,
+            Err(error) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(error.into());
+            }
+        };
+        uploads.insert(job_id.clone(), ChunkUpload { dir, file, len: 0 });
+    }
+    let upload = uploads.get_mut(&job_id).expect("upload was inserted above");
+    let appended = check_audio_size(upload.len + chunk.len() as u64, max_audio_bytes(&app))
+        .and_then(|_| upload.file.write_all(&chunk).map_err(CommandError::from));
+    if let Err(error) = appended {
+        if let Some(upload) = uploads.remove(&job_id) {
+            let _ = fs::remove_dir_all(&upload.dir);
+        }
+        return Err(error);
+    }
+    upload.len += chunk.len() as u64;
+    if !is_final {
+        return Ok(None);
+    }
+
+    let Some(upload) = uploads.remove(&job_id) else {
+        return Ok(None);
+    };
+    drop(uploads);
+    drop(upload.file);
+    let audio_bytes = fs::read(upload.dir.join("upload.bin"));
+    let _ = fs::remove_dir_all(&upload.dir);
+    whisper_transcribe_command(
+        &app,
+        audio_bytes?,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+    .map(Some)
+}
+
+// Kills the running whisper process of a job started with a `jobId` and runs
+// it again on the same audio with `language` (when given) and `options`,
+// emitting `whisper:restarted`. The original call returns the new result.
+// Returns false when no job with that id is running.
+#[tauri::command]
+fn whisper_retry(
+    app: tauri::AppHandle,
+    job_id: String,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> bool {
+    let state = app.state::<WhisperState>();
+    let mut jobs = lock_recovering(&state.jobs, "whisper jobs");
+    let Some(job) = jobs.get_mut(&job_id) else {
+        return false;
+    };
+    job.retry = Some(WhisperRetry {
+        language: resolve_non_empty(language),
+        options: options.unwrap_or_default(),
+    });
+    // Between two runs (e.g. while converting audio) there is nothing to
+    // kill; the current run finishes and its result is discarded.
+    if let Some(pid) = job.pid {
+        kill_pid(pid);
+    }
+    true
+}
+
+// Abandons an upload started with `whisper_transcribe_chunks` and removes its
+// temp file. Returns false when no upload with that id is in progress.
+#[tauri::command]
+fn whisper_cancel_chunks(app: tauri::AppHandle, job_id: String) -> bool {
+    let state = app.state::<WhisperState>();
+    let upload = lock_recovering(&state.uploads, "whisper uploads").remove(&job_id);
+    match upload {
+        Some(upload) => {
+            drop(upload.file);
+            let _ = fs::remove_dir_all(&upload.dir);
+            true
+        }
+        None => false,
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperPassPayload {
+    // "original" or "translated".
+    pass: &'static str,
+    index: u32,
+    total: u32,
+}
+
+// `both`: transcribes the audio in its own language and then translates it
+// to English. The translation pass reads the first pass's prepared input WAV
+// from disk, so audio is only converted once. `whisper:pass` is emitted as
+// each pass starts.
+fn whisper_transcribe_both(
+    app: &tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: &WhisperOptions,
+) -> CommandResult<TranscriptResult> {
+    if options.output_path.is_some() {
+        return Err(CommandError::InvalidArgument(
+            "outputPath can't be used with both; the two passes would write the same file."
+                .to_string(),
+        ));
+    }
+    let emit_pass = |pass, index| {
+        let _ = app.emit(
+            "whisper:pass",
+            WhisperPassPayload {
+                pass,
+                index,
+                total: 2,
+            },
+        );
+    };
+    // Dry and chunked runs leave no single prepared input behind, so their
+    // second pass needs the audio itself.
+    let reuse_input = !options.dry_run.unwrap_or(false) && options.chunk_ms.is_none();
+    let (first_bytes, spare_bytes) = if reuse_input {
+        (audio_bytes, Vec::new())
+    } else {
+        (audio_bytes.clone(), audio_bytes)
+    };
+
+    emit_pass("original", 1);
+    let mut original = whisper_transcribe_tracked(
+        app,
+        first_bytes,
+        whisper_binary.clone(),
+        model_path.clone(),
+        language.clone(),
+        &WhisperOptions {
+            translate: Some(false),
+            both: None,
+            keep_input: reuse_input,
+            ..options.clone()
+        },
+    )?;
+
+    emit_pass("translated", 2);
+    let translated = whisper_transcribe_tracked(
+        app,
+        spare_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &WhisperOptions {
+            translate: Some(true),
+            both: None,
+            prepared_input: original.input_path.clone().map(PathBuf::from),
+            ..options.clone()
+        },
+    );
+    // The first pass's directory only outlived it for the second pass.
+    if !options.keep_artifacts.unwrap_or(false) {
+        if let Some(dir) = original.artifacts_dir.take() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        original.input_path = None;
+    }
+    original.translated = Some(Box::new(translated?));
+    Ok(original)
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EnsembleRun {
+    model_path: String,
+    confidence: Option<f32>,
+    transcript: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EnsembleResult {
+    best: TranscriptResult,
+    best_model: String,
+    runs: Vec<EnsembleRun>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EnsembleProgressPayload {
+    model_path: String,
+    completed: usize,
+    total: usize,
+    confidence: Option<f32>,
+}
+
+// An ensemble entry is either a model path or a file name found by the
+// bundled model search, with or without the `ggml-` prefix and `.bin`
+// extension ("medium", "ggml-medium.bin").
+fn resolve_ensemble_model(app: &tauri::AppHandle, name: &str) -> CommandResult<String> {
+    let expanded = paths::expand_path(name.trim());
+    if Path::new(&expanded).is_file() {
+        return Ok(expanded);
+    }
+    let bare = expanded
+        .trim_start_matches("ggml-")
+        .trim_end_matches(".bin");
+    let wanted = format!("ggml-{bare}.bin");
+    collect_whisper_models(app)
+        .into_iter()
+        .find(|path| model_file_name(path).eq_ignore_ascii_case(&wanted))
+        .map(|path| path.to_string_lossy().to_string())
+        .ok_or_else(|| {
+            CommandError::InvalidArgument(format!(
+                "Ensemble model '{name}' was not found as a path or among the bundled models."
+            ))
+        })
+}
+
+// Runs the same audio through each of `models` in turn (each through the
+// queue, so they don't compete for the CPU) and returns the transcript with
+// the highest confidence, plus how every model did. Emits
+// `whisper:ensemble_progress` after each run. Failed runs are reported in
+// the breakdown; the command only fails if every model does.
+#[tauri::command(async)]
+fn whisper_transcribe_ensemble(
+    app: tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    models: Vec<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<EnsembleResult> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    if models.len() < 2 {
+        return Err(CommandError::InvalidArgument(
+            "An ensemble needs at least two models.".to_string(),
+        ));
+    }
+    let model_paths = models
+        .iter()
+        .map(|name| resolve_ensemble_model(&app, name))
+        .collect::<CommandResult<Vec<_>>>()?;
+    let options = options.unwrap_or_default();
+    if options.output_path.is_some() || options.dry_run == Some(true) {
+        return Err(CommandError::InvalidArgument(
+            "outputPath and dryRun can't be used with an ensemble.".to_string(),
+        ));
+    }
+    // A threshold is what turns on token-level output, which the confidence
+    // is computed from; 0 never triggers `whisper:low_confidence`.
+    let run_options = WhisperOptions {
+        min_confidence: Some(options.min_confidence.unwrap_or(0.0)),
+        model_fallback: Some(false),
+        ..options
+    };
+
+    let mut runs = Vec::with_capacity(model_paths.len());
+    let mut best: Option<(TranscriptResult, String)> = None;
+    let mut last_error = None;
+    for model_path in model_paths {
+        let outcome = whisper_transcribe_local_impl(
+            &app,
+            audio_bytes.clone(),
+            whisper_binary.clone(),
+            Some(model_path.clone()),
+            language.clone(),
+            &run_options,
+        );
+        let run = match outcome {
+            Ok(result) => {
+                let run = EnsembleRun {
+                    model_path: model_path.clone(),
+                    confidence: result.confidence,
+                    transcript: Some(result.transcript.clone()),
+                    error: None,
+                };
+                let better = match &best {
+                    Some((current, _)) => {
+                        result.confidence.unwrap_or(0.0) > current.confidence.unwrap_or(0.0)
+                    }
+                    None => true,
+                };
+                if better {
+                    best = Some((result, model_path.clone()));
+                }
+                run
+            }
+            Err(error) => {
+                let run = EnsembleRun {
+                    model_path: model_path.clone(),
+                    confidence: None,
+                    transcript: None,
+                    error: Some(error.to_string()),
+                };
+                last_error = Some(error);
+                run
+            }
+        };
+        let _ = app.emit(
+            "whisper:ensemble_progress",
+            EnsembleProgressPayload {
+                model_path,
+                completed: runs.len() + 1,
+                total: models.len(),
+                confidence: run.confidence,
+            },
+        );
+        runs.push(run);
+    }
+
+    match best {
+        Some((best, best_model)) => {
+            run_configured_post_hook(&app, &best, language.as_deref(), &run_options);
+            Ok(EnsembleResult {
+                best,
+                best_model,
+                runs,
+            })
+        }
+        None => Err(last_error
+            .unwrap_or_else(|| CommandError::Whisper("No ensemble model ran.".to_string()))
+            .with_detail("every ensemble model failed")),
+    }
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_words(
+    app: tauri::AppHandle,
+    audio_bytes: Vec<u8>,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<Vec<WordTiming>> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let options = WhisperOptions {
+        word_timestamps: Some(true),
+        ..options.unwrap_or_default()
+    };
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options,
+    )
+    .map(|result| result.words)
+}
+
+const MAX_MICROPHONE_SECONDS: u32 = 600;
+
+#[tauri::command]
+fn list_audio_devices() -> CommandResult<Vec<microphone::AudioDevice>> {
+    microphone::list_input_devices()
+}
+
+#[tauri::command(async)]
+fn whisper_transcribe_microphone(
+    app: tauri::AppHandle,
+    device: Option<String>,
+    duration_secs: u32,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+    language: Option<String>,
+    options: Option<WhisperOptions>,
+) -> CommandResult<String> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    if !(1..=MAX_MICROPHONE_SECONDS).contains(&duration_secs) {
+        return Err(CommandError::InvalidArgument(format!(
+            "durationSecs must be between 1 and {MAX_MICROPHONE_SECONDS} (got {duration_secs})."
+        )));
+    }
+    let audio_bytes = microphone::record_wav(
+        resolve_non_empty(device).as_deref(),
+        Duration::from_secs(u64::from(duration_secs)),
+    )?;
+    whisper_transcribe_command(
+        &app,
+        audio_bytes,
+        whisper_binary,
+        model_path,
+        language,
+        &options.unwrap_or_default(),
+    )
+    .map(|result| result.transcript)
+}
+
+#[derive(Serialize, Clone)]
+struct DetectedLanguage {
+    language: String,
+    confidence: Option<f32>,
+}
+
+// whisper.cpp reports the probe result as
+// `auto-detected language: de (p = 0.954321)`.
+fn parse_detected_language(output: &str) -> Option<DetectedLanguage> {
+    const MARKER: &str = "auto-detected language:";
+    let line = output.lines().find(|line| line.contains(MARKER))?;
+    let details = line[line.find(MARKER)? + MARKER.len()..].trim();
+    let language = details.split_whitespace().next()?.to_string();
+    let confidence = details.split("p =").nth(1).and_then(|value| {
+        value
+            .trim()
+            .trim_end_matches(')')
+            .trim()
+            .parse::<f32>()
+            .ok()
+    });
+    Some(DetectedLanguage {
+        language,
+        confidence,
+    })
 }
 
-fn is_tiny_whisper_model_path(model_path: &str) -> bool {
-    Path::new(model_path)
-        .file_name()
-        .and_then(|candidate| candidate.to_str())
-        .map(|candidate| candidate.to_ascii_lowercase().contains("ggml-tiny"))
-        .unwrap_or(false)
+// Every language code whisper accepts, with its English name, for a
+// language picker. "auto" isn't included.
+#[tauri::command]
+fn whisper_supported_languages() -> Vec<languages::Language> {
+    languages::supported_languages()
 }
 
-fn whisper_transcribe_local_impl(
-    app: &tauri::AppHandle,
+#[tauri::command(async)]
+fn whisper_detect_language(
+    app: tauri::AppHandle,
     audio_bytes: Vec<u8>,
     whisper_binary: Option<String>,
     model_path: Option<String>,
-    language: Option<String>,
-) -> Result<String, String> {
+) -> CommandResult<DetectedLanguage> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
     if audio_bytes.is_empty() {
-        return Err("No audio payload received.".to_string());
+        return Err(CommandError::InvalidArgument(
+            "No audio payload received.".to_string(),
+        ));
     }
+    check_audio_size(audio_bytes.len() as u64, max_audio_bytes(&app))?;
 
-    let resolved_binary = resolve_non_empty(whisper_binary)
-        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_BIN").ok()))
-        .or_else(|| {
-            resolve_bundled_resource_candidates(
-                app,
-                &[
-                    "whisper-cli.exe",
-                    "whisper-cli",
-                    "whisper/whisper-cli.exe",
-                    "resources/whisper/whisper-cli.exe",
-                    "whisper/whisper-cli",
-                    "resources/whisper/whisper-cli",
-                ],
-                &["whisper-cli.exe", "whisper-cli"],
-            )
-        })
-        .unwrap_or_else(|| "whisper-cli".to_string());
+    let resolved_binary = resolve_whisper_binary(&app, whisper_binary);
+    let resolved_model_path = resolve_whisper_model_path(&app, model_path)?;
+    let working_dir = create_whisper_working_dir()?;
+    // Passed through unconverted, so the file is named for what it is.
+    let input_name = format!(
+        "input.{}",
+        audio::sniff_container(&audio_bytes).unwrap_or("audio")
+    );
+    let input_audio_path = write_whisper_input(&working_dir, &input_name, &audio_bytes)?;
 
-    let resolved_model_path = resolve_non_empty(model_path)
-        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_MODEL_PATH").ok()))
-        .or_else(|| {
-            resolve_bundled_resource_candidates(
-                app,
-                &[
-                    "ggml-large-v3.bin",
-                    "ggml-large-v3-turbo.bin",
-                    "ggml-medium.bin",
-                    "ggml-medium.en.bin",
-                    "ggml-base.bin",
-                    "ggml-base.en.bin",
-                    "ggml-tiny.en.bin",
-                    "ggml-tiny.bin",
-                    "whisper/ggml-large-v3.bin",
-                    "resources/whisper/ggml-large-v3.bin",
-                    "whisper/ggml-large-v3-turbo.bin",
-                    "resources/whisper/ggml-large-v3-turbo.bin",
-                    "whisper/ggml-medium.bin",
-                    "resources/whisper/ggml-medium.bin",
-                    "whisper/ggml-medium.en.bin",
-                    "resources/whisper/ggml-medium.en.bin",
-                    "whisper/ggml-base.bin",
-                    "resources/whisper/ggml-base.bin",
-                    "whisper/ggml-base.en.bin",
-                    "resources/whisper/ggml-base.en.bin",
-                    "whisper/ggml-tiny.en.bin",
-                    "resources/whisper/ggml-tiny.en.bin",
-                    "whisper/ggml-tiny.bin",
-                    "resources/whisper/ggml-tiny.bin",
-                ],
-                &[
-                    "ggml-large-v3.bin",
-                    "ggml-large-v3-turbo.bin",
-                    "ggml-large-v2.bin",
-                    "ggml-large-v1.bin",
-                    "ggml-large.bin",
-                    "ggml-medium.bin",
-                    "ggml-medium.en.bin",
-                    "ggml-small.bin",
-                    "ggml-small.en.bin",
-                    "ggml-base.bin",
-                    "ggml-base.en.bin",
-                    "ggml-tiny.en.bin",
-                    "ggml-tiny.bin",
-                ],
-            )
-        })
-        .or_else(|| {
-            for root in collect_resource_search_roots(app) {
-                if let Some(found_model) = find_whisper_model_recursively(&root) {
-                    return Some(found_model.to_string_lossy().to_string());
-                }
-            }
-            None
-        })
-        .ok_or_else(|| {
-            "Whisper model path is missing. Set GREEPY_WHISPER_MODEL_PATH, pass modelPath, or select a larger local model file such as ggml-large-v3.bin.".to_string()
-        })?;
-    if is_tiny_whisper_model_path(&resolved_model_path) {
-        return Err(
-            "Tiny Whisper models are disabled. Select a larger model such as ggml-large-v3.bin."
-                .to_string(),
-        );
+    let mut whisper_command = Command::new(&resolved_binary);
+    whisper_command
+        .arg("-m")
+        .arg(&resolved_model_path)
+        .arg("-f")
+        .arg(&input_audio_path)
+        .arg("-l")
+        .arg("auto")
+        .arg("--detect-language");
+    hide_console_window(&mut whisper_command);
+    let whisper_state = app.state::<WhisperState>();
+    let permit = whisper_state.queue.acquire();
+    let process_output = whisper_command.output();
+    drop(permit);
+    let _ = fs::remove_dir_all(&working_dir);
+    let process_output = process_output.map_err(|error| {
+        CommandError::SpawnFailed(format!(
+            "Failed to launch whisper binary '{resolved_binary}': {error}"
+        ))
+    })?;
+
+    let stderr = String::from_utf8_lossy(&process_output.stderr);
+    let stdout = String::from_utf8_lossy(&process_output.stdout);
+    if !process_output.status.success() {
+        return Err(CommandError::Whisper(format!(
+            "Whisper language detection failed: status: {} | stderr: {}",
+            format_exit_status(&process_output.status),
+            stderr.trim()
+        )));
     }
 
-    let resolved_language = resolve_non_empty(language)
-        .or_else(|| resolve_non_empty(std::env::var("GREEPY_WHISPER_LANGUAGE").ok()))
-        .unwrap_or_else(|| "auto".to_string());
+    parse_detected_language(&stderr)
+        .or_else(|| parse_detected_language(&stdout))
+        .ok_or_else(|| {
+            CommandError::Whisper("Whisper did not report a detected language.".to_string())
+        })
+}
 
-    let stamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|duration| duration.as_millis())
-        .unwrap_or(0);
-    let working_dir = std::env::temp_dir().join(format!("greepy-whisper-{stamp}"));
-    fs::create_dir_all(&working_dir).map_err(|error| error.to_string())?;
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperWarmedPayload {
+    model_path: String,
+    load_ms: u64,
+}
 
-    let input_audio_path = working_dir.join("input.wav");
-    let output_base_path = working_dir.join("transcript");
-    fs::write(&input_audio_path, audio_bytes).map_err(|error| {
-        let _ = fs::remove_dir_all(&working_dir);
-        format!("Failed to write temporary audio file: {error}")
-    })?;
+// Runs whisper once over a second of silence so the model file is pulled into
+// the OS page cache before the first real dictation. whisper-cli has no
+// resident mode, so every later run still loads the model, just from memory.
+#[tauri::command(async)]
+fn whisper_warmup(
+    app: tauri::AppHandle,
+    whisper_binary: Option<String>,
+    model_path: Option<String>,
+) -> CommandResult<u64> {
+    if SPEECH_TO_TEXT_DISABLED {
+        return Err(CommandError::Disabled);
+    }
+    let resolved_binary = resolve_whisper_binary(&app, whisper_binary);
+    let resolved_model_path = resolve_whisper_model_path(&app, model_path)?;
+    let working_dir = create_whisper_working_dir()?;
+    let input_audio_path =
+        write_whisper_input(&working_dir, "input.wav", &audio::silent_wav(1000))?;
 
     let mut whisper_command = Command::new(&resolved_binary);
     whisper_command
@@ -543,122 +6136,492 @@ fn whisper_transcribe_local_impl(
         .arg("-f")
         .arg(&input_audio_path)
         .arg("-l")
-        .arg(&resolved_language)
-        .arg("-otxt")
-        .arg("-of")
-        .arg(&output_base_path);
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-        whisper_command.creation_flags(CREATE_NO_WINDOW);
-    }
-    let process_output = whisper_command.output().map_err(|error| {
-        let _ = fs::remove_dir_all(&working_dir);
-        format!("Failed to launch whisper binary '{resolved_binary}': {error}")
+        .arg("en");
+    hide_console_window(&mut whisper_command);
+    let whisper_state = app.state::<WhisperState>();
+    let permit = whisper_state.queue.acquire();
+    let started_at = Instant::now();
+    let process_output = whisper_command.output();
+    let load_ms = started_at.elapsed().as_millis() as u64;
+    drop(permit);
+    let _ = fs::remove_dir_all(&working_dir);
+    let process_output = process_output.map_err(|error| {
+        CommandError::SpawnFailed(format!(
+            "Failed to launch whisper binary '{resolved_binary}': {error}"
+        ))
     })?;
-
     if !process_output.status.success() {
-        let stderr = String::from_utf8_lossy(&process_output.stderr)
-            .trim()
-            .to_string();
-        let stdout = String::from_utf8_lossy(&process_output.stdout)
-            .trim()
-            .to_string();
-        let _ = fs::remove_dir_all(&working_dir);
-        let mut details = Vec::new();
-        details.push(format!(
-            "status: {}",
-            format_exit_status(&process_output.status)
-        ));
-        if !stderr.is_empty() {
-            details.push(format!("stderr: {stderr}"));
-        }
-        if !stdout.is_empty() {
-            details.push(format!("stdout: {stdout}"));
-        }
-        if stderr.is_empty() && stdout.is_empty() {
-            details.push("No process output captured.".to_string());
-        }
-        details.push(format!("binary: {resolved_binary}"));
-        details.push(format!("model: {resolved_model_path}"));
-        if let Some(hint) = whisper_runtime_hint(process_output.status.code()) {
-            details.push(hint.to_string());
-        }
-        return Err(format!(
-            "Whisper transcription failed: {}",
-            details.join(" | ")
-        ));
+        return Err(CommandError::Whisper(format!(
+            "Whisper warmup failed: status: {} | stderr: {}",
+            format_exit_status(&process_output.status),
+            String::from_utf8_lossy(&process_output.stderr).trim()
+        )));
     }
 
-    let transcript_file = output_base_path.with_extension("txt");
-    let transcript = fs::read_to_string(&transcript_file).map_err(|error| {
-        let _ = fs::remove_dir_all(&working_dir);
-        format!("Failed to read transcript output: {error}")
-    })?;
+    let _ = app.emit(
+        "whisper:warmed",
+        WhisperWarmedPayload {
+            model_path: resolved_model_path,
+            load_ms,
+        },
+    );
+    Ok(load_ms)
+}
 
-    let _ = fs::remove_dir_all(&working_dir);
-    let cleaned = transcript.trim();
-    if cleaned.is_empty() {
-        return Err("Transcription completed but no speech was detected.".to_string());
-    }
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SearchRoot {
+    path: String,
+    binary_path: Option<String>,
+    model_path: Option<String>,
+}
 
-    Ok(cleaned.to_string())
+#[tauri::command]
+fn whisper_list_models(app: tauri::AppHandle) -> Vec<WhisperModelInfo> {
+    let mut models: Vec<WhisperModelInfo> = collect_whisper_models(&app)
+        .iter()
+        .map(|path| whisper_model_info(path))
+        .collect();
+    models.sort_by_key(|model| (whisper_model_priority(&model.file_name), model.path.clone()));
+    models
 }
 
+// Diagnostic dump of the directories the bundled binary/model search walks,
+// with whatever each one would contribute on its own.
 #[tauri::command]
-fn whisper_transcribe_local(
-    app: tauri::AppHandle,
-    audio_bytes: Vec<u8>,
-    whisper_binary: Option<String>,
+fn whisper_search_roots(app: tauri::AppHandle) -> Vec<SearchRoot> {
+    describe_search_roots(&app)
+}
+
+fn describe_search_roots(app: &tauri::AppHandle) -> Vec<SearchRoot> {
+    collect_resource_search_roots(app)
+        .into_iter()
+        .map(|root| {
+            let binary_path = ["whisper-cli.exe", "whisper-cli"]
+                .iter()
+                .find_map(|file_name| find_file_recursively(&root, file_name));
+            let model_path = find_whisper_model_recursively(&root);
+            SearchRoot {
+                path: root.to_string_lossy().to_string(),
+                binary_path: binary_path.map(|path| path.to_string_lossy().to_string()),
+                model_path: model_path.map(|path| path.to_string_lossy().to_string()),
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WhisperDiagnosis {
+    search_roots: Vec<SearchRoot>,
+    // `None` when no binary was found and whisper would be looked up on PATH.
+    binary_path: Option<String>,
     model_path: Option<String>,
-    language: Option<String>,
-) -> Result<String, String> {
-    if SPEECH_TO_TEXT_DISABLED {
-        return Err("Speech-to-text is disabled in this build.".to_string());
+    // Tiny models that were found but are never used.
+    tiny_models: Vec<String>,
+    // Where the binary and models are expected, i.e. the bundled
+    // `resources/whisper` directory.
+    expected_dir: String,
+    expected_dir_created: bool,
+    problems: Vec<String>,
+}
+
+// Checks the whisper setup the way a transcription would resolve it and
+// explains what is missing. With `create_dir`, the expected
+// `resources/whisper` directory is created if it doesn't exist yet.
+#[tauri::command]
+fn whisper_diagnose(app: tauri::AppHandle, create_dir: Option<bool>) -> WhisperDiagnosis {
+    let resolved_binary = resolve_whisper_binary(&app, None);
+    let binary_path = Path::new(&resolved_binary)
+        .is_file()
+        .then_some(resolved_binary);
+    let resolved_model = resolve_whisper_model_path(&app, None);
+    let tiny_models: Vec<String> = collect_whisper_models(&app)
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .filter(|path| is_tiny_whisper_model_path(path))
+        .collect();
+    let expected_dir = app
+        .path()
+        .resource_dir()
+        .ok()
+        .or_else(|| {
+            std::env::current_exe()
+                .ok()
+                .and_then(|path| path.parent().map(Path::to_path_buf))
+        })
+        .unwrap_or_default()
+        .join("resources")
+        .join("whisper");
+    let mut expected_dir_created = false;
+    let mut problems = Vec::new();
+    if create_dir.unwrap_or(false) && !expected_dir.is_dir() {
+        match fs::create_dir_all(&expected_dir) {
+            Ok(()) => expected_dir_created = true,
+            Err(error) => problems.push(format!(
+                "Could not create '{}': {error}",
+                expected_dir.display()
+            )),
+        }
+    }
+    let expected = expected_dir.display();
+    if binary_path.is_none() {
+        problems.push(format!(
+            "No whisper-cli binary was found; place it in '{expected}', set GREEPY_WHISPER_BIN, or put whisper-cli on PATH."
+        ));
+    }
+    match &resolved_model {
+        Ok(_) => {}
+        Err(_) if !tiny_models.is_empty() => problems.push(format!(
+            "Only tiny models were found, and those are disabled. Place a larger model such as ggml-base.bin or ggml-large-v3.bin in '{expected}'."
+        )),
+        Err(error) => problems.push(format!("{error} Models are searched for in '{expected}'.")),
+    }
+    WhisperDiagnosis {
+        search_roots: describe_search_roots(&app),
+        binary_path,
+        model_path: resolved_model.ok(),
+        tiny_models,
+        expected_dir: expected_dir.to_string_lossy().to_string(),
+        expected_dir_created,
+        problems,
     }
-    whisper_transcribe_local_impl(&app, audio_bytes, whisper_binary, model_path, language)
 }
 
 #[tauri::command]
-fn whisper_transcribe_local_file(
+fn whisper_capabilities(
     app: tauri::AppHandle,
-    audio_path: String,
     whisper_binary: Option<String>,
-    model_path: Option<String>,
-    language: Option<String>,
-) -> Result<String, String> {
-    if SPEECH_TO_TEXT_DISABLED {
-        return Err("Speech-to-text is disabled in this build.".to_string());
-    }
-    let trimmed_audio_path = audio_path.trim();
-    if trimmed_audio_path.is_empty() {
-        return Err("Audio file path is missing.".to_string());
-    }
-    let audio_bytes = fs::read(trimmed_audio_path)
-        .map_err(|error| format!("Failed to read audio file '{trimmed_audio_path}': {error}"))?;
-    whisper_transcribe_local_impl(&app, audio_bytes, whisper_binary, model_path, language)
+) -> CommandResult<WhisperCapabilities> {
+    let resolved_binary = resolve_whisper_binary(&app, whisper_binary);
+    let capabilities = probe_whisper_capabilities(&resolved_binary)?;
+    lock_recovering(
+        &app.state::<WhisperState>().capabilities,
+        "whisper capabilities",
+    )
+    .insert(resolved_binary, capabilities.clone());
+    Ok(capabilities)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    thread::spawn(|| {
+        let _ = sweep_stale_whisper_dirs(&whisper_temp_base(), STALE_WHISPER_TEMP_DIR_AGE);
+    });
+
     tauri::Builder::default()
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .setup(|app| {
+            logging::init(app.path().app_log_dir().ok().as_deref());
+            let config_path = app
+                .path()
+                .app_config_dir()
+                .ok()
+                .map(|dir| dir.join(CONFIG_FILE_NAME));
+            app.manage(ConfigState::load(config_path));
+            if let Some(interval) = reap_interval() {
+                let app_handle = app.handle().clone();
+                thread::spawn(move || loop {
+                    thread::sleep(interval);
+                    reap_exited_sessions(&app_handle);
+                });
+            }
+            Ok(())
+        })
         .manage(PtyManager {
             sessions: Mutex::new(HashMap::new()),
+            pipes: Mutex::new(HashMap::new()),
+            spawned_pids: Mutex::new(HashMap::new()),
+            system: Mutex::new(sysinfo::System::new()),
+            shells: Mutex::new(None),
+        })
+        .manage(WhisperState {
+            capabilities: Mutex::new(HashMap::new()),
+            queue: TranscriptionQueue::from_env(),
+            uploads: Mutex::new(HashMap::new()),
+            jobs: Mutex::new(HashMap::new()),
+            failures: Mutex::new(VecDeque::new()),
         })
         .invoke_handler(tauri::generate_handler![
             pty_create,
             pty_write,
+            pty_write_slow,
+            pty_write_sync,
+            pty_send_key,
+            pty_broadcast,
+            pty_expect,
+            pty_report_size,
+            pty_set_focus,
+            pty_get_mode,
+            pty_set_mode,
+            pty_get_screen,
+            pty_pause,
+            pty_resume,
+            pty_reset,
+            pty_get_scrollback,
+            pty_get_scrollback_snapshot,
+            pty_export_scrollback,
+            pty_tail,
+            pty_clear,
             pty_resize,
+            pty_resize_pixels,
+            pty_resize_all,
             pty_close,
+            pty_shutdown,
+            pty_get_env,
+            list_shells,
+            pty_restart,
+            pty_clone,
+            kill_orphans,
+            pty_is_alive,
+            pty_session_count,
+            pty_stats,
+            pty_start_io_log,
+            pty_stop_io_log,
+            pty_pipe,
+            pty_unpipe,
             whisper_transcribe_local,
-            whisper_transcribe_local_file
+            whisper_transcribe_local_file,
+            whisper_transcribe_local_detailed,
+            whisper_transcribe_local_file_detailed,
+            whisper_transcribe_chunks,
+            whisper_cancel_chunks,
+            whisper_retry,
+            whisper_last_errors,
+            whisper_transcribe_words,
+            whisper_transcribe_ensemble,
+            whisper_transcribe_microphone,
+            list_audio_devices,
+            whisper_transcribe_directory,
+            whisper_detect_language,
+            whisper_supported_languages,
+            whisper_capabilities,
+            whisper_search_roots,
+            whisper_list_models,
+            whisper_diagnose,
+            whisper_warmup,
+            read_file_chunked,
+            disk_space,
+            get_config,
+            set_config
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<PtyManager>().shutdown();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_recovering_survives_a_poisoned_mutex() {
+        let mutex = Mutex::new(vec![1u8]);
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut guard = mutex.lock().unwrap();
+                    guard.push(2);
+                    panic!("poison the lock");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert!(mutex.is_poisoned());
+
+        let mut guard = lock_recovering(&mutex, "test");
+        assert_eq!(*guard, vec![1, 2]);
+        guard.push(3);
+        drop(guard);
+        assert!(!mutex.is_poisoned());
+        assert_eq!(*lock_recovering(&mutex, "test"), vec![1, 2, 3]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty_write_still_reaches_the_pty_after_the_sessions_lock_is_poisoned() {
+        let manager = PtyManager::default();
+        let (session, reader) = spawn_test_session("cat", &[]);
+        let output = forward_output(reader);
+        manager.sessions().insert("poisoned".to_string(), session);
+
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _sessions = manager.sessions();
+                    panic!("poison the sessions lock");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert!(manager.sessions.is_poisoned());
+
+        // The same steps pty_write takes.
+        let mut sessions = manager.sessions();
+        let session = sessions.get_mut("poisoned").unwrap();
+        write_to_session(session, b"still writable\n").unwrap();
+        drop(sessions);
+        wait_for_output(&output, &mut String::new(), "still writable");
+        assert!(!manager.sessions.is_poisoned());
+    }
+
+    // The kernel only sends SIGWINCH when the size actually changes, so a
+    // resize to the current size is only noticed through the explicit signal.
+    #[cfg(unix)]
+    #[test]
+    fn resize_signals_the_foreground_group_even_when_the_size_is_unchanged() {
+        let (mut session, reader) = spawn_test_session(
+            "sh",
+            &[
+                "-c",
+                "trap 'echo winch' WINCH; echo ready; while :; do sleep 0.05; done",
+            ],
+        );
+        let output = forward_output(reader);
+        let mut seen = String::new();
+        wait_for_output(&output, &mut seen, "ready");
+
+        let size = session.config.pty_size();
+        resize_session(&mut session, size).unwrap();
+        wait_for_output(&output, &mut seen, "winch");
+        let _ = session.child.kill();
+        let _ = session.child.wait();
+    }
+
+    // Spawns `program` on an 80x24 PTY and wraps it in a Session the way
+    // pty_create does, returning the reader for its output alongside.
+    #[cfg(unix)]
+    fn spawn_test_session(program: &str, args: &[&str]) -> (Session, Box<dyn Read + Send>) {
+        let size = PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        let pair = native_pty_system().openpty(size).unwrap();
+        let mut cmd = CommandBuilder::new(program);
+        cmd.args(args);
+        let child = pair.slave.spawn_command(cmd).unwrap();
+        drop(pair.slave);
+        let reader = pair.master.try_clone_reader().unwrap();
+        let writer = Arc::new(Mutex::new(pair.master.take_writer().unwrap()));
+        let session = Session {
+            pid: child.process_id(),
+            master: pair.master,
+            writer,
+            child,
+            env: HashMap::new(),
+            config: SessionConfig {
+                cols: size.cols,
+                rows: size.rows,
+                pixel_width: 0,
+                pixel_height: 0,
+                cwd: String::new(),
+                options: PtyCreateOptions::default(),
+            },
+            shared: Arc::default(),
+        };
+        (session, reader)
+    }
+
+    // Forwards everything read from a PTY to a channel, one chunk per read.
+    #[cfg(unix)]
+    fn forward_output(mut reader: Box<dyn Read + Send>) -> std::sync::mpsc::Receiver<String> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+            while let Ok(count) = reader.read(&mut buffer) {
+                let chunk = String::from_utf8_lossy(&buffer[..count]).into_owned();
+                if count == 0 || sender.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    // Collects output into `seen` until it contains `needle`, failing the test
+    // after five seconds.
+    #[cfg(unix)]
+    fn wait_for_output(
+        output: &std::sync::mpsc::Receiver<String>,
+        seen: &mut String,
+        needle: &str,
+    ) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !seen.contains(needle) {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            match output.recv_timeout(remaining) {
+                Ok(chunk) => seen.push_str(&chunk),
+                Err(_) => panic!("{needle:?} never appeared in {seen:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn bell_scanner_skips_bells_that_end_osc_sequences() {
+        let mut scanner = BellScanner::default();
+        assert_eq!(scanner.count(b"\x07ding\x07"), 2);
+        // A title set through OSC and ended by BEL isn't a bell, and neither
+        // is anything before an OSC ended by ST.
+        assert_eq!(scanner.count(b"\x1b]0;title\x07prompt$ "), 0);
+        assert_eq!(scanner.count(b"\x1b]2;t\x1b\\\x07"), 1);
+        // The state carries across reads.
+        assert_eq!(scanner.count(b"\x1b"), 0);
+        assert_eq!(scanner.count(b"]0;split\x07"), 0);
+        assert_eq!(scanner.count(b"\x07"), 1);
+    }
+
+    #[test]
+    fn size_query_scanner_finds_only_size_queries() {
+        let mut scanner = SizeQueryScanner::default();
+        assert_eq!(scanner.scan(b"\x1b[18tx\x1b[14t\x1b[19t"), [18, 14, 19]);
+        // Other window operations, private sequences and other finals are
+        // left alone.
+        assert!(scanner
+            .scan(b"\x1b[8;24;80t\x1b[18m\x1b[?18t\x1b[t\x1b[21t[18t")
+            .is_empty());
+        // The state carries across reads.
+        assert!(scanner.scan(b"\x1b[1").is_empty());
+        assert_eq!(scanner.scan(b"8t"), [18]);
+    }
+
+    #[test]
+    fn wildcard_match_is_case_insensitive_and_backtracks() {
+        assert!(wildcard_match("*.wav", "Meeting.WAV"));
+        assert!(wildcard_match("take?.wav", "take1.wav"));
+        assert!(!wildcard_match("take?.wav", "take10.wav"));
+        assert!(wildcard_match("a*b*c", "abxbbc"));
+        assert!(wildcard_match("*a*b*", "xxaxxbxx"));
+        assert!(!wildcard_match("*.wav", "notes.wav.txt"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("?", ""));
+    }
+
+    #[test]
+    fn transcript_confidence_averages_text_token_log_probabilities() {
+        // Timestamp tokens and blanks don't count; 0.8 and 0.2 average to 0.4.
+        let json = r#"{"transcription": [
+            {"tokens": [{"text": "[_BEG_]", "p": 0.1}, {"text": " hello", "p": 0.8}]},
+            {"tokens": [{"text": " ", "p": 0.01}, {"text": " world", "p": 0.2}]}
+        ]}"#;
+        let confidence = transcript_confidence(json).unwrap();
+        assert!((confidence - 0.4).abs() < 1e-6, "{confidence}");
+        // A zero probability is clamped instead of sending the mean to 0.
+        let json = r#"{"transcription": [{"tokens": [
+            {"text": "a", "p": 0.0}, {"text": "b", "p": 1.0}
+        ]}]}"#;
+        assert!((transcript_confidence(json).unwrap() - 1e-3).abs() < 1e-6);
+
+        assert_eq!(transcript_confidence(r#"{"transcription": []}"#), None);
+        assert_eq!(transcript_confidence("not json"), None);
+    }
 }
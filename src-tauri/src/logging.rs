@@ -0,0 +1,28 @@
+use std::path::Path;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+pub const LOG_FILE_NAME: &str = "greepy.log";
+const DEFAULT_LOG_FILTER: &str = "info";
+
+// Logs go to stderr and to a daily-rotated file in `log_dir` when there is
+// one. `GREEPY_LOG` takes an env-filter directive such as `debug` or
+// `greepy_lib=trace`; invalid directives fall back to `info`.
+pub fn init(log_dir: Option<&Path>) {
+    let filter = std::env::var("GREEPY_LOG")
+        .ok()
+        .and_then(|directive| EnvFilter::try_new(directive).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let file_layer = log_dir
+        .filter(|dir| std::fs::create_dir_all(dir).is_ok())
+        .map(|dir| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(tracing_appender::rolling::daily(dir, LOG_FILE_NAME))
+        });
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(file_layer)
+        .try_init();
+}
@@ -0,0 +1,141 @@
+use crate::audio::{pcm16_wav, WHISPER_SAMPLE_RATE};
+use crate::error::{CommandError, CommandResult};
+use crate::lock_recovering;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioDevice {
+    name: String,
+    is_default: bool,
+}
+
+fn audio_error(error: impl std::fmt::Display) -> CommandError {
+    CommandError::Io(format!("Audio input error: {error}"))
+}
+
+pub fn list_input_devices() -> CommandResult<Vec<AudioDevice>> {
+    let host = cpal::default_host();
+    let default_name = host
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+    let devices = host.input_devices().map_err(audio_error)?;
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| AudioDevice {
+            is_default: default_name.as_deref() == Some(name.as_str()),
+            name,
+        })
+        .collect())
+}
+
+fn find_input_device(name: Option<&str>) -> CommandResult<cpal::Device> {
+    let host = cpal::default_host();
+    let Some(name) = name else {
+        return host.default_input_device().ok_or_else(|| {
+            CommandError::InvalidArgument("No default audio input device is available.".to_string())
+        });
+    };
+    host.input_devices()
+        .map_err(audio_error)?
+        .find(|device| {
+            device
+                .name()
+                .map(|candidate| candidate == name)
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| {
+            CommandError::InvalidArgument(format!("Audio input device '{name}' was not found."))
+        })
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Mutex<Vec<f32>>>,
+) -> CommandResult<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = usize::from(config.channels.max(1));
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let mut samples = lock_recovering(&samples, "microphone samples");
+                // Downmix every frame to mono.
+                samples.extend(data.chunks(channels).map(|frame| {
+                    frame
+                        .iter()
+                        .map(|sample| f32::from_sample(*sample))
+                        .sum::<f32>()
+                        / frame.len() as f32
+                }));
+            },
+            |error| tracing::warn!("audio input stream error: {error}"),
+            None,
+        )
+        .map_err(audio_error)
+}
+
+// Linear interpolation is plenty for speech headed into whisper.
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = f64::from(from_rate) / f64::from(to_rate);
+    let output_len = (samples.len() as f64 / ratio) as usize;
+    (0..output_len)
+        .map(|index| {
+            let position = index as f64 * ratio;
+            let base = position as usize;
+            let fraction = (position - base as f64) as f32;
+            let current = samples[base];
+            let next = samples.get(base + 1).copied().unwrap_or(current);
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+// Records from the named input device (or the system default) and returns a
+// 16 kHz mono 16-bit WAV ready for whisper.
+pub fn record_wav(device_name: Option<&str>, duration: Duration) -> CommandResult<Vec<u8>> {
+    let device = find_input_device(device_name)?;
+    let supported = device.default_input_config().map_err(audio_error)?;
+    let sample_rate = supported.sample_rate().0;
+    let config: cpal::StreamConfig = supported.config();
+    let samples = Arc::new(Mutex::new(Vec::new()));
+
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, samples.clone())?,
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, samples.clone())?,
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, samples.clone())?,
+        cpal::SampleFormat::I32 => build_stream::<i32>(&device, &config, samples.clone())?,
+        format => {
+            return Err(CommandError::Io(format!(
+                "Unsupported audio input sample format: {format}"
+            )))
+        }
+    };
+    stream.play().map_err(audio_error)?;
+    thread::sleep(duration);
+    drop(stream);
+
+    let recorded = std::mem::take(&mut *lock_recovering(&samples, "microphone samples"));
+    if recorded.is_empty() {
+        return Err(CommandError::Io(
+            "No audio was captured from the input device.".to_string(),
+        ));
+    }
+    let pcm: Vec<i16> = resample(&recorded, sample_rate, WHISPER_SAMPLE_RATE)
+        .into_iter()
+        .map(|sample| (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16)
+        .collect();
+    Ok(pcm16_wav(&pcm))
+}
@@ -0,0 +1,112 @@
+use std::env;
+use std::path::PathBuf;
+
+#[cfg(windows)]
+const HOME_VAR: &str = "USERPROFILE";
+#[cfg(not(windows))]
+const HOME_VAR: &str = "HOME";
+
+// Expands a leading `~` and environment variables in a user-supplied path:
+// `$VAR` / `${VAR}` on Unix, `%VAR%` on Windows. Unset variables are left as
+// written so the eventual "not found" error still shows what was asked for.
+pub fn expand_path(path: &str) -> String {
+    expand_variables(&expand_tilde(path))
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os(HOME_VAR)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+fn expand_tilde(path: &str) -> String {
+    let Some(rest) = path.strip_prefix('~') else {
+        return path.to_string();
+    };
+    let at_boundary =
+        rest.is_empty() || rest.starts_with('/') || (cfg!(windows) && rest.starts_with('\\'));
+    match home_dir() {
+        Some(home) if at_boundary => format!("{}{rest}", home.to_string_lossy()),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(not(windows))]
+fn expand_variables(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, consumed) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match env::var(name).ok().filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[start..start + 1 + consumed]),
+        }
+        rest = &after[consumed..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(windows)]
+fn expand_variables(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &after[..end];
+        match env::var(name).ok().filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One test, since it changes the process environment.
+    #[cfg(not(windows))]
+    #[test]
+    fn expand_path_replaces_home_and_set_variables() {
+        env::set_var("HOME", "/home/tester");
+        env::set_var("GREEPY_TEST_DIR", "models");
+        env::remove_var("GREEPY_TEST_UNSET");
+
+        assert_eq!(expand_path("~"), "/home/tester");
+        assert_eq!(expand_path("~/a/b"), "/home/tester/a/b");
+        // `~user` isn't supported and stays as written.
+        assert_eq!(expand_path("~other/a"), "~other/a");
+        assert_eq!(expand_path("/x/~/y"), "/x/~/y");
+
+        assert_eq!(expand_path("$GREEPY_TEST_DIR/base.bin"), "models/base.bin");
+        assert_eq!(expand_path("${GREEPY_TEST_DIR}x"), "modelsx");
+        assert_eq!(expand_path("~/$GREEPY_TEST_DIR"), "/home/tester/models");
+        assert_eq!(
+            expand_path("$GREEPY_TEST_UNSET/${GREEPY_TEST_UNSET}"),
+            "$GREEPY_TEST_UNSET/${GREEPY_TEST_UNSET}"
+        );
+        assert_eq!(expand_path("cost: $5 and $"), "cost: $5 and $");
+        assert_eq!(expand_path("${unterminated"), "${unterminated");
+    }
+}
@@ -0,0 +1,672 @@
+use vte::{Params, Parser, Perform};
+
+// A deliberately small terminal model: enough cursor movement, erasing and
+// scrolling to reconstruct what a shell or TUI shows, without attributes,
+// colors or the alternate screen buffer.
+pub struct Screen {
+    parser: Parser,
+    grid: Grid,
+}
+
+impl Screen {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Screen {
+            parser: Parser::new(),
+            grid: Grid::new(cols, rows),
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.parser.advance(&mut self.grid, *byte);
+        }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.grid.resize(cols, rows);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.grid
+            .cells
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim_end().to_string())
+            .collect()
+    }
+}
+
+// Removes escape sequences and control characters other than newlines and
+// tabs, leaving the text a program printed.
+pub fn strip_ansi(text: &str) -> String {
+    struct PlainText(String);
+
+    impl Perform for PlainText {
+        fn print(&mut self, character: char) {
+            self.0.push(character);
+        }
+
+        fn execute(&mut self, byte: u8) {
+            if byte == b'\n' || byte == b'\t' {
+                self.0.push(char::from(byte));
+            }
+        }
+    }
+
+    let mut parser = Parser::new();
+    let mut plain = PlainText(String::with_capacity(text.len()));
+    for byte in text.as_bytes() {
+        parser.advance(&mut plain, *byte);
+    }
+    plain.0
+}
+
+type Rgb = (u8, u8, u8);
+
+// xterm's default 16-color palette.
+const ANSI_PALETTE: [Rgb; 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+const HTML_FOREGROUND: Rgb = (229, 229, 229);
+const HTML_BACKGROUND: Rgb = (0, 0, 0);
+
+fn palette_color(index: u16) -> Rgb {
+    match index {
+        0..=15 => ANSI_PALETTE[usize::from(index)],
+        // 6x6x6 color cube.
+        16..=231 => {
+            let level = |value: u16| {
+                if value == 0 {
+                    0
+                } else {
+                    (value * 40 + 55) as u8
+                }
+            };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let gray = (8 + (index.min(255) - 232) * 10) as u8;
+            (gray, gray, gray)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct TextStyle {
+    foreground: Option<Rgb>,
+    background: Option<Rgb>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+impl TextStyle {
+    fn apply_sgr(&mut self, params: &Params) {
+        // Colon sub-parameters (`38:2:r:g:b`) are read like their
+        // semicolon-separated equivalents.
+        let values: Vec<u16> = params.iter().flatten().copied().collect();
+        if values.is_empty() {
+            *self = TextStyle::default();
+        }
+        let mut index = 0;
+        while index < values.len() {
+            match values[index] {
+                0 => *self = TextStyle::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                code @ 30..=37 => self.foreground = Some(ANSI_PALETTE[usize::from(code - 30)]),
+                code @ 40..=47 => self.background = Some(ANSI_PALETTE[usize::from(code - 40)]),
+                code @ 90..=97 => self.foreground = Some(ANSI_PALETTE[usize::from(code - 82)]),
+                code @ 100..=107 => self.background = Some(ANSI_PALETTE[usize::from(code - 92)]),
+                39 => self.foreground = None,
+                49 => self.background = None,
+                code @ (38 | 48) => {
+                    let color = match values.get(index + 1) {
+                        Some(5) => values.get(index + 2).map(|value| {
+                            index += 2;
+                            palette_color(*value)
+                        }),
+                        Some(2) if values.len() > index + 4 => {
+                            let channel = |offset: usize| values[index + offset].min(255) as u8;
+                            let rgb = (channel(2), channel(3), channel(4));
+                            index += 4;
+                            Some(rgb)
+                        }
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.foreground = color.or(self.foreground);
+                    } else {
+                        self.background = color.or(self.background);
+                    }
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    fn css(&self) -> String {
+        let (mut foreground, mut background) = (self.foreground, self.background);
+        if self.inverse {
+            (foreground, background) = (
+                Some(background.unwrap_or(HTML_BACKGROUND)),
+                Some(foreground.unwrap_or(HTML_FOREGROUND)),
+            );
+        }
+        let mut css = String::new();
+        if let Some((r, g, b)) = foreground {
+            css.push_str(&format!("color:#{r:02x}{g:02x}{b:02x};"));
+        }
+        if let Some((r, g, b)) = background {
+            css.push_str(&format!("background-color:#{r:02x}{g:02x}{b:02x};"));
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        css
+    }
+}
+
+// Renders terminal output as a standalone HTML page, with SGR colors and
+// attributes turned into styled spans. Cursor movement isn't replayed, so
+// full-screen programs come out as their raw text.
+pub fn ansi_to_html(text: &str) -> String {
+    struct HtmlText {
+        html: String,
+        style: TextStyle,
+        // Style of the currently open span, if one is open.
+        open: Option<TextStyle>,
+    }
+
+    impl HtmlText {
+        fn sync_span(&mut self) {
+            if self.open == Some(self.style)
+                || (self.open.is_none() && self.style == TextStyle::default())
+            {
+                return;
+            }
+            if self.open.take().is_some() {
+                self.html.push_str("</span>");
+            }
+            if self.style != TextStyle::default() {
+                self.html
+                    .push_str(&format!("<span style=\"{}\">", self.style.css()));
+                self.open = Some(self.style);
+            }
+        }
+    }
+
+    impl Perform for HtmlText {
+        fn print(&mut self, character: char) {
+            self.sync_span();
+            match character {
+                '&' => self.html.push_str("&amp;"),
+                '<' => self.html.push_str("&lt;"),
+                '>' => self.html.push_str("&gt;"),
+                '"' => self.html.push_str("&quot;"),
+                _ => self.html.push(character),
+            }
+        }
+
+        fn execute(&mut self, byte: u8) {
+            if byte == b'\n' || byte == b'\t' {
+                self.html.push(char::from(byte));
+            }
+        }
+
+        fn csi_dispatch(
+            &mut self,
+            params: &Params,
+            intermediates: &[u8],
+            _ignore: bool,
+            action: char,
+        ) {
+            if action == 'm' && intermediates.is_empty() {
+                self.style.apply_sgr(params);
+            }
+        }
+    }
+
+    let (r, g, b) = HTML_FOREGROUND;
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Terminal output</title>\n</head>\n<body style=\"margin:0;background:#000\">\n<pre style=\"margin:0;padding:8px;color:#{r:02x}{g:02x}{b:02x};font-family:monospace\">"
+    );
+    let mut parser = Parser::new();
+    let mut writer = HtmlText {
+        html: String::with_capacity(text.len()),
+        style: TextStyle::default(),
+        open: None,
+    };
+    for byte in text.as_bytes() {
+        parser.advance(&mut writer, *byte);
+    }
+    html.push_str(&writer.html);
+    if writer.open.is_some() {
+        html.push_str("</span>");
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+struct Grid {
+    cells: Vec<Vec<char>>,
+    cols: usize,
+    rows: usize,
+    row: usize,
+    col: usize,
+    saved: (usize, usize),
+    scroll_top: usize,
+    scroll_bottom: usize,
+    // Set after printing into the last column; the next character wraps.
+    wrap_pending: bool,
+}
+
+impl Grid {
+    fn new(cols: u16, rows: u16) -> Self {
+        let cols = usize::from(cols.max(1));
+        let rows = usize::from(rows.max(1));
+        Grid {
+            cells: vec![vec![' '; cols]; rows],
+            cols,
+            rows,
+            row: 0,
+            col: 0,
+            saved: (0, 0),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            wrap_pending: false,
+        }
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        let cols = usize::from(cols.max(1));
+        let rows = usize::from(rows.max(1));
+        // Shrinking drops lines from the top so the cursor line stays visible.
+        if self.row >= rows {
+            self.cells.drain(..self.row + 1 - rows);
+            self.row = rows - 1;
+        }
+        self.cells.resize(rows, vec![' '; cols]);
+        for line in &mut self.cells {
+            line.resize(cols, ' ');
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.col = self.col.min(cols - 1);
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.wrap_pending = false;
+    }
+
+    fn blank_line(&self) -> Vec<char> {
+        vec![' '; self.cols]
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count {
+            self.cells.remove(self.scroll_top);
+            self.cells.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count {
+            self.cells.remove(self.scroll_bottom);
+            self.cells.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn erase(&mut self, row: usize, from: usize, to: usize) {
+        for cell in &mut self.cells[row][from.min(self.cols)..to.min(self.cols)] {
+            *cell = ' ';
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+}
+
+// First CSI parameter, with 0 and "missing" both meaning `default`.
+fn param(params: &Params, index: usize, default: usize) -> usize {
+    params
+        .iter()
+        .nth(index)
+        .and_then(|values| values.first())
+        .map(|value| usize::from(*value))
+        .filter(|value| *value != 0)
+        .unwrap_or(default)
+}
+
+impl Perform for Grid {
+    fn print(&mut self, character: char) {
+        if self.wrap_pending {
+            self.col = 0;
+            self.line_feed();
+            self.wrap_pending = false;
+        }
+        self.cells[self.row][self.col] = character;
+        if self.col + 1 < self.cols {
+            self.col += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => {
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            0x08 => {
+                self.col = self.col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        // Private modes (`CSI ? ...`) toggle features this model doesn't track.
+        if !intermediates.is_empty() {
+            return;
+        }
+        let (row, col) = (self.row, self.col);
+        match action {
+            'A' => self.move_to(row.saturating_sub(param(params, 0, 1)), col),
+            'B' | 'e' => self.move_to(row + param(params, 0, 1), col),
+            'C' | 'a' => self.move_to(row, col + param(params, 0, 1)),
+            'D' => self.move_to(row, col.saturating_sub(param(params, 0, 1))),
+            'E' => self.move_to(row + param(params, 0, 1), 0),
+            'F' => self.move_to(row.saturating_sub(param(params, 0, 1)), 0),
+            'G' | '`' => self.move_to(row, param(params, 0, 1) - 1),
+            'd' => self.move_to(param(params, 0, 1) - 1, col),
+            'H' | 'f' => self.move_to(param(params, 0, 1) - 1, param(params, 1, 1) - 1),
+            'J' => match param(params, 0, 0) {
+                0 => {
+                    self.erase(row, col, self.cols);
+                    for line in row + 1..self.rows {
+                        self.erase(line, 0, self.cols);
+                    }
+                }
+                1 => {
+                    for line in 0..row {
+                        self.erase(line, 0, self.cols);
+                    }
+                    self.erase(row, 0, col + 1);
+                }
+                _ => {
+                    for line in 0..self.rows {
+                        self.erase(line, 0, self.cols);
+                    }
+                }
+            },
+            'K' => match param(params, 0, 0) {
+                0 => self.erase(row, col, self.cols),
+                1 => self.erase(row, 0, col + 1),
+                _ => self.erase(row, 0, self.cols),
+            },
+            'X' => self.erase(row, col, col + param(params, 0, 1)),
+            'P' => {
+                let count = param(params, 0, 1).min(self.cols - col);
+                let line = &mut self.cells[row];
+                line.drain(col..col + count);
+                line.resize(self.cols, ' ');
+                // As in xterm, the next character lands at the cursor
+                // instead of wrapping.
+                self.wrap_pending = false;
+            }
+            '@' => {
+                let count = param(params, 0, 1).min(self.cols - col);
+                let line = &mut self.cells[row];
+                for _ in 0..count {
+                    line.insert(col, ' ');
+                }
+                line.truncate(self.cols);
+                self.wrap_pending = false;
+            }
+            'L' | 'M' if (self.scroll_top..=self.scroll_bottom).contains(&row) => {
+                let top = self.scroll_top;
+                self.scroll_top = row;
+                if action == 'L' {
+                    self.scroll_down(param(params, 0, 1));
+                } else {
+                    self.scroll_up(param(params, 0, 1));
+                }
+                self.scroll_top = top;
+            }
+            'S' => self.scroll_up(param(params, 0, 1)),
+            'T' => self.scroll_down(param(params, 0, 1)),
+            'r' => {
+                let top = param(params, 0, 1) - 1;
+                let bottom = param(params, 1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            's' => self.saved = (row, col),
+            'u' => self.move_to(self.saved.0, self.saved.1),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        match byte {
+            b'7' => self.saved = (self.row, self.col),
+            b'8' => self.move_to(self.saved.0, self.saved.1),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            b'M' => {
+                if self.row == self.scroll_top {
+                    self.scroll_down(1);
+                } else {
+                    self.row = self.row.saturating_sub(1);
+                }
+            }
+            b'c' => *self = Grid::new(self.cols as u16, self.rows as u16),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(cols: u16, rows: u16, input: &str) -> Vec<String> {
+        let mut screen = Screen::new(cols, rows);
+        screen.feed(input.as_bytes());
+        screen.lines()
+    }
+
+    #[test]
+    fn cursor_movement_is_clamped_to_the_screen() {
+        // CUP is 1-based; CUU, CUF, CUB and CUD stop at the edges.
+        assert_eq!(
+            render(10, 3, "\x1b[2;3Ha\x1b[Ab\x1b[5Cc\x1b[20Dd\x1b[9Be"),
+            ["d  b     c", "  a", " e"]
+        );
+        assert_eq!(
+            render(10, 3, "ab\x1b[sxy\x1b[3;5Hz\x1b[uq\x1b[1E\x1b[4Gr"),
+            ["abqy", "   r", "    z"]
+        );
+    }
+
+    const FILLED: &str = "abcdefgh\r\nabcdefgh\r\nabcdefgh";
+
+    #[test]
+    fn erase_in_line_modes() {
+        let erase =
+            |sequence: &str| render(8, 3, &format!("{FILLED}\x1b[2;4H{sequence}"))[1].clone();
+        assert_eq!(erase("\x1b[K"), "abc");
+        assert_eq!(erase("\x1b[1K"), "    efgh");
+        assert_eq!(erase("\x1b[2K"), "");
+        assert_eq!(erase("\x1b[2X"), "abc  fgh");
+    }
+
+    #[test]
+    fn erase_in_display_modes() {
+        let erase = |sequence: &str| render(8, 3, &format!("{FILLED}\x1b[2;4H{sequence}"));
+        assert_eq!(erase("\x1b[J"), ["abcdefgh", "abc", ""]);
+        assert_eq!(erase("\x1b[1J"), ["", "    efgh", "abcdefgh"]);
+        assert_eq!(erase("\x1b[2J"), ["", "", ""]);
+    }
+
+    #[test]
+    fn scrolling_stays_inside_the_scroll_region() {
+        let lines = "1\r\n2\r\n3\r\n4\r\n5";
+        // A line feed on the region's bottom line scrolls only the region.
+        assert_eq!(
+            render(4, 5, &format!("{lines}\x1b[2;4r\x1b[4;1H\nx")),
+            ["1", "3", "4", "x", "5"]
+        );
+        // So does a reverse index on its top line, the other way.
+        assert_eq!(
+            render(4, 5, &format!("{lines}\x1b[2;4r\x1b[2;1H\x1bMx")),
+            ["1", "x", "2", "3", "5"]
+        );
+        assert_eq!(
+            render(4, 5, &format!("{lines}\x1b[2;4r\x1b[2S")),
+            ["1", "4", "", "", "5"]
+        );
+        // Without a region the whole screen scrolls.
+        assert_eq!(render(4, 3, "1\r\n2\r\n3\r\n4"), ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn printing_in_the_last_column_defers_the_wrap() {
+        assert_eq!(render(4, 3, "abcd"), ["abcd", "", ""]);
+        // CR LF right after a full line doesn't leave an empty line behind.
+        assert_eq!(render(4, 3, "abcd\r\nx"), ["abcd", "x", ""]);
+        assert_eq!(render(4, 3, "abcde"), ["abcd", "e", ""]);
+        // Moving the cursor cancels the pending wrap.
+        assert_eq!(render(4, 3, "abcd\x1b[Dx"), ["abxd", "", ""]);
+        assert_eq!(render(4, 3, "abcd\rx"), ["xbcd", "", ""]);
+        // Wrapping on the bottom line scrolls.
+        assert_eq!(render(2, 2, "abcdef"), ["cd", "ef"]);
+    }
+
+    #[test]
+    fn delete_and_insert_characters_at_the_right_margin() {
+        assert_eq!(render(5, 1, "abcde\x1b[P"), ["abcd"]);
+        assert_eq!(render(5, 1, "abcde\x1b[Px"), ["abcdx"]);
+        assert_eq!(render(5, 1, "abcde\x1b[9P"), ["abcd"]);
+        assert_eq!(render(5, 1, "abcde\x1b[3D\x1b[9P"), ["a"]);
+        assert_eq!(render(5, 1, "abcde\x1b[2G\x1b[2P"), ["ade"]);
+        assert_eq!(render(5, 1, "abcde\x1b[@"), ["abcd"]);
+        assert_eq!(render(5, 1, "abcde\x1b[9@x"), ["abcdx"]);
+        assert_eq!(render(5, 1, "abcde\x1b[G\x1b[2@"), ["  abc"]);
+    }
+
+    #[test]
+    fn strip_ansi_keeps_printed_text_newlines_and_tabs() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mred\x1b[0m\ttab\r\n\x1b]0;title\x07\x1b[2Kdone\x07"),
+            "red\ttab\ndone"
+        );
+        assert_eq!(strip_ansi("plain text"), "plain text");
+    }
+
+    // The text inside `<pre>`, without the page around it.
+    fn html_body(input: &str) -> String {
+        let html = ansi_to_html(input);
+        let start = html.find("monospace\">").unwrap() + "monospace\">".len();
+        let end = html.rfind("</pre>").unwrap();
+        html[start..end].to_string()
+    }
+
+    #[test]
+    fn ansi_to_html_reads_extended_colors_with_either_separator() {
+        let red_text = "<span style=\"color:#ff000a;\">x</span>";
+        assert_eq!(html_body("\x1b[38;2;255;0;10mx"), red_text);
+        assert_eq!(html_body("\x1b[38:2:255:0:10mx"), red_text);
+        // Palette entry 196 is pure red in the 6x6x6 cube.
+        let red_background = "<span style=\"background-color:#ff0000;\">x</span>";
+        assert_eq!(html_body("\x1b[48;5;196mx"), red_background);
+        assert_eq!(html_body("\x1b[48:5:196mx"), red_background);
+        // Parameters after the color still apply.
+        assert_eq!(
+            html_body("\x1b[38;5;1;1mx"),
+            "<span style=\"color:#cd0000;font-weight:bold;\">x</span>"
+        );
+        assert_eq!(
+            html_body("\x1b[38:5:1;48;5;244mx"),
+            "<span style=\"color:#cd0000;background-color:#808080;\">x</span>"
+        );
+    }
+
+    #[test]
+    fn ansi_to_html_closes_spans_and_escapes_text() {
+        assert_eq!(
+            html_body("\x1b[1mx\x1b[0my<&>\"\x1b[7mz"),
+            "<span style=\"font-weight:bold;\">x</span>y&lt;&amp;&gt;&quot;<span style=\"color:#000000;background-color:#e5e5e5;\">z</span>"
+        );
+    }
+
+    #[test]
+    fn resize_keeps_the_cursor_line() {
+        let mut screen = Screen::new(4, 3);
+        screen.feed(b"1\r\n2\r\n3");
+        screen.resize(2, 2);
+        assert_eq!(screen.lines(), ["2", "3"]);
+        screen.feed(b"x");
+        assert_eq!(screen.lines(), ["2", "3x"]);
+    }
+}
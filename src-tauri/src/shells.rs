@@ -0,0 +1,71 @@
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize, Clone)]
+pub struct ShellInfo {
+    pub name: String,
+    pub path: String,
+}
+
+#[cfg(not(windows))]
+pub fn detect_shells() -> Vec<ShellInfo> {
+    let listed = std::fs::read_to_string("/etc/shells").unwrap_or_default();
+    let mut seen = std::collections::HashSet::new();
+    let mut shells = Vec::new();
+    for line in listed.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || !Path::new(line).is_file() {
+            continue;
+        }
+        // /bin/bash and /usr/bin/bash are often the same file on merged-/usr
+        // systems; only the first listed path is kept.
+        let canonical = std::fs::canonicalize(line).unwrap_or_else(|_| line.into());
+        if !seen.insert(canonical) {
+            continue;
+        }
+        let name = Path::new(line)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(line)
+            .to_string();
+        shells.push(ShellInfo {
+            name,
+            path: line.to_string(),
+        });
+    }
+    shells
+}
+
+#[cfg(windows)]
+pub fn detect_shells() -> Vec<ShellInfo> {
+    const CANDIDATES: &[(&str, &str)] = &[
+        ("Command Prompt", r"%SystemRoot%\System32\cmd.exe"),
+        (
+            "Windows PowerShell",
+            r"%SystemRoot%\System32\WindowsPowerShell\v1.0\powershell.exe",
+        ),
+        ("PowerShell", r"%ProgramFiles%\PowerShell\7\pwsh.exe"),
+        (
+            "PowerShell",
+            r"%LOCALAPPDATA%\Microsoft\WindowsApps\pwsh.exe",
+        ),
+        ("Git Bash", r"%ProgramFiles%\Git\bin\bash.exe"),
+        ("Git Bash", r"%ProgramFiles(x86)%\Git\bin\bash.exe"),
+        ("Git Bash", r"%LOCALAPPDATA%\Programs\Git\bin\bash.exe"),
+        ("WSL", r"%SystemRoot%\System32\wsl.exe"),
+    ];
+    let mut shells: Vec<ShellInfo> = Vec::new();
+    for (name, template) in CANDIDATES {
+        // Several install locations share a name; the first one found wins.
+        if shells.iter().any(|shell| shell.name == *name) {
+            continue;
+        }
+        let path = crate::paths::expand_path(template);
+        if !path.contains('%') && Path::new(&path).is_file() {
+            shells.push(ShellInfo {
+                name: name.to_string(),
+                path,
+            });
+        }
+    }
+    shells
+}
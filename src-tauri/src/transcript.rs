@@ -0,0 +1,176 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+// Square-bracket tags such as `[BLANK_AUDIO]`, `[MUSIC]` or `[ Silence ]` are
+// removed wherever they appear; whisper doesn't produce square brackets for
+// spoken text. Parenthesized cues like `(wind blowing)` are only removed when
+// they make up a whole segment line, so asides inside a sentence survive.
+fn annotation_patterns() -> &'static (Regex, Regex) {
+    static PATTERNS: OnceLock<(Regex, Regex)> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        (
+            Regex::new(r"\[\s*[A-Za-z][A-Za-z _'-]{0,40}\]").expect("valid annotation pattern"),
+            Regex::new(r"^\s*[(*]\s*[A-Za-z][A-Za-z ,'-]{0,60}[)*]\s*$")
+                .expect("valid annotation pattern"),
+        )
+    })
+}
+
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Joins transcripts of two overlapping audio windows. The end of `previous`
+// and the start of `next` cover the same audio, so the longest run of words
+// they share (ignoring case and punctuation) is kept once. Words cut off at
+// a window edge are often garbled, so the run may start up to two words into
+// `next` and end up to two words before the end of `previous`; those edge
+// words are dropped. Without a match the texts are simply concatenated.
+pub fn stitch_overlap(previous: &str, next: &str, max_overlap_words: usize) -> String {
+    const EDGE_WORDS: usize = 2;
+    let previous_words: Vec<&str> = previous.split_whitespace().collect();
+    let next_words: Vec<&str> = next.split_whitespace().collect();
+    let previous_norm: Vec<String> = previous_words.iter().map(|w| normalize_word(w)).collect();
+    let next_norm: Vec<String> = next_words.iter().map(|w| normalize_word(w)).collect();
+
+    // (matched words, words trimmed from `previous`, words skipped in `next`)
+    let mut best: Option<(usize, usize, usize)> = None;
+    for trim in 0..=EDGE_WORDS.min(previous_words.len()) {
+        for skip in 0..=EDGE_WORDS.min(next_words.len()) {
+            let head = &previous_norm[..previous_norm.len() - trim];
+            let tail = &next_norm[skip..];
+            let longest = max_overlap_words.min(head.len()).min(tail.len());
+            let matched = (1..=longest)
+                .rev()
+                .find(|len| head[head.len() - len..] == tail[..*len]);
+            // A single shared word only counts when it sits right at both cut
+            // edges; elsewhere it's too likely to be a coincidence.
+            let Some(matched) = matched.filter(|len| *len > 1 || (trim == 0 && skip == 0)) else {
+                continue;
+            };
+            if !matches!(best, Some((best_len, ..)) if best_len >= matched) {
+                best = Some((matched, trim, skip));
+            }
+        }
+    }
+
+    let (kept_previous, next_start) = match best {
+        Some((matched, trim, skip)) => (previous_words.len() - trim, skip + matched),
+        None => (previous_words.len(), 0),
+    };
+    previous_words[..kept_previous]
+        .iter()
+        .chain(&next_words[next_start..])
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Rule-based cleanup for models that emit lowercase, unpunctuated text: the
+// first letter of every sentence and the pronoun "I" are capitalized, and
+// each line that ends mid-sentence gets a period. Already punctuated text
+// only has its whitespace tidied.
+pub fn normalize_sentences(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let mut normalized = String::with_capacity(line.len() + 1);
+            let mut sentence_start = true;
+            for (index, word) in line.split_whitespace().enumerate() {
+                if index > 0 {
+                    normalized.push(' ');
+                }
+                let lower = word.to_lowercase();
+                let bare = lower.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '\'');
+                let is_pronoun = matches!(bare, "i" | "i'm" | "i'll" | "i've" | "i'd");
+                let mut characters = word.chars();
+                match characters.next() {
+                    Some(first) if sentence_start || is_pronoun => {
+                        normalized.extend(first.to_uppercase());
+                        normalized.push_str(characters.as_str());
+                    }
+                    _ => normalized.push_str(word),
+                }
+                if word.chars().any(char::is_alphanumeric) {
+                    sentence_start = false;
+                }
+                if word.ends_with(['.', '!', '?']) {
+                    sentence_start = true;
+                }
+            }
+            if normalized.ends_with(char::is_alphanumeric) {
+                normalized.push('.');
+            }
+            normalized
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn strip_annotations(text: &str) -> String {
+    let (bracketed, standalone) = annotation_patterns();
+    text.lines()
+        .filter(|line| !standalone.is_match(line))
+        .map(|line| {
+            bracketed
+                .replace_all(line, "")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stitch_overlap_keeps_the_shared_words_once() {
+        assert_eq!(
+            stitch_overlap("the quick brown fox", "brown fox jumps over", 10),
+            "the quick brown fox jumps over"
+        );
+        // Case and punctuation don't prevent a match; `previous` keeps its
+        // spelling.
+        assert_eq!(
+            stitch_overlap("so we went Home.", "went home, and then slept", 10),
+            "so we went Home. and then slept"
+        );
+    }
+
+    #[test]
+    fn stitch_overlap_drops_garbled_words_at_the_cut() {
+        // "ju" and "mps" are the halves of a word cut at the window edges.
+        assert_eq!(
+            stitch_overlap("the quick brown fox ju", "mps brown fox jumps over", 10),
+            "the quick brown fox jumps over"
+        );
+        assert_eq!(
+            stitch_overlap("one two three four fiv", "ree three four five six", 10),
+            "one two three four five six"
+        );
+    }
+
+    #[test]
+    fn stitch_overlap_concatenates_without_a_real_match() {
+        assert_eq!(
+            stitch_overlap("the cat sat", "a dog ran", 10),
+            "the cat sat a dog ran"
+        );
+        // A single common word away from the edges is a coincidence.
+        assert_eq!(
+            stitch_overlap("I saw the cat sat", "uh the dog ran", 10),
+            "I saw the cat sat uh the dog ran"
+        );
+        // Only runs up to the cap are considered.
+        assert_eq!(stitch_overlap("x y z", "y z w", 10), "x y z w");
+        assert_eq!(stitch_overlap("x y z", "y z w", 1), "x y z y z w");
+        assert_eq!(stitch_overlap("", "hello there", 10), "hello there");
+        assert_eq!(stitch_overlap("hello there", "", 10), "hello there");
+    }
+}
@@ -735,8 +735,13 @@ const saveStoredWhisperPath = (key: string, value: string) => {
   window.localStorage.setItem(key, trimmed);
 };
 
-const formatErrorMessage = (error: unknown) =>
-  error instanceof Error ? error.message : String(error);
+const formatErrorMessage = (error: unknown) => {
+  if (error instanceof Error) return error.message;
+  if (error && typeof error === "object" && "message" in error) {
+    return String((error as { message: unknown }).message);
+  }
+  return String(error);
+};
 
 let ptyCreateQueue: Promise<void> = Promise.resolve();
 const startupCommandExecuted = new Set<string>();